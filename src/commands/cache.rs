@@ -0,0 +1,73 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use serde::Serialize;
+use crate::error::Result;
+use crate::utils::backend::GitBackend;
+use crate::utils::cache::{Cache, CacheEntry};
+use crate::utils::output::{self, OutputFormat};
+use crate::utils::repo::Repository;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    pub action: Action,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Action {
+    /// Remove all cached data, it is rebuilt on the next run
+    Clear,
+    /// Show which refs are cached and how much space they take
+    Stats,
+}
+
+/// JSON document printed by `cache stats`
+#[derive(Serialize)]
+struct StatsOutput {
+    path: String,
+    /// Total size of the cache files in bytes
+    size: u64,
+    refs: Vec<CacheEntry>,
+}
+
+/// Handle the cache command - inspect or clear `.git/bp-cache/`
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    let repo = Repository::open()?;
+    let cache = Cache::new(repo.git_path("bp-cache")?);
+
+    match args.action {
+        Action::Clear => {
+            cache.clear()?;
+            if format == OutputFormat::Text {
+                eprintln!("Cleared {}", cache.dir().display());
+            }
+        }
+        Action::Stats => {
+            let refs = cache.entries()?;
+            let size = cache.size()?;
+            match format {
+                OutputFormat::Text => {
+                    println!("Cache directory: {}", cache.dir().display());
+                    for entry in &refs {
+                        let tip = entry.tip.as_deref().map_or("(invalid)", |tip| repo.object_format().abbreviate(tip));
+                        println!("{}\t{}\t{} segment(s)", entry.ref_name, tip, entry.segments);
+                    }
+                    println!("Total: {} refs, {} bytes", refs.len(), size);
+                }
+                OutputFormat::Json => {
+                    output::print_json(&StatsOutput {
+                        path: cache.dir().display().to_string(),
+                        size,
+                        refs,
+                    })?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,167 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::collections::HashSet;
+use log::debug;
+use serde::Serialize;
+use crate::error::{Error, Result};
+use crate::commands::{fix, sort};
+use crate::utils::backend::GitBackend;
+use crate::utils::commits::{CommitEntry, CommitInfo, CommitsParser};
+use crate::utils::output::{self, OutputFormat};
+use crate::utils::repo::Repository;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// File containing the commits to check (one per line)
+    #[arg(long = "commits-file", short = 'F', required = true)]
+    pub commits_file: String,
+
+    /// Reference branch the commits come from
+    #[arg(long = "ref", required = true)]
+    pub ref_branch: String,
+
+    /// Insert the prerequisites into the commits file, before the first commit needing them
+    #[arg(long = "in-place", short = 'i')]
+    pub in_place: bool,
+}
+
+/// JSON document printed by the deps command
+#[derive(Serialize)]
+struct DepsReport {
+    commits: Vec<DependentCommit>,
+}
+
+/// A commit from the commits file and the upstream commits it needs
+#[derive(Serialize)]
+struct DependentCommit {
+    #[serde(flatten)]
+    commit: CommitInfo,
+    /// Missing upstream commits that last touched the lines this commit changes, oldest first
+    prerequisites: Vec<CommitInfo>,
+}
+
+/// Handle the deps command - find missing upstream commits the listed commits depend on
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    let repo = Repository::open()?;
+
+    let (modelines, mut entries) = CommitsParser::read_from_file(&args.commits_file)?;
+    for entry in &mut entries {
+        entry.commit.expand_hash_to_full(&repo)?;
+        entry.commit.fetch_change_id_if_missing(&repo)?;
+        entry.commit.fetch_title_if_missing(&repo)?;
+    }
+
+    // Upstream commits before the fork point are on HEAD already
+    let base = repo.merge_base("HEAD", &args.ref_branch)?
+        .ok_or_else(|| Error::NoCommonAncestor("HEAD".to_string(), args.ref_branch.clone()))?;
+    let candidates: HashSet<String> = repo.range(&base, &args.ref_branch)?.iter().cloned().collect();
+    let listed: HashSet<String> = entries.iter().map(|entry| entry.commit.hash.clone()).collect();
+
+    let mut report = DepsReport {
+        commits: Vec::new(),
+    };
+    for entry in &entries {
+        let mut blamed = HashSet::new();
+        let parent = format!("{}^", entry.commit.hash);
+        for file in repo.pre_image_lines(&entry.commit.hash)? {
+            blamed.extend(repo.blame(&parent, &file.path, &file.ranges)?);
+        }
+        debug!("Lines changed by {} were last touched by {} commit(s)", entry.commit.hash, blamed.len());
+
+        let missing: Vec<String> = blamed.into_iter()
+            .filter(|hash| candidates.contains(hash) && !listed.contains(hash))
+            .collect();
+        // Oldest first, so that inserting them in order applies cleanly
+        let missing = oldest_first(&repo, missing, &args.ref_branch)?;
+
+        let mut prerequisites = Vec::new();
+        for hash in missing {
+            let mut prerequisite = CommitInfo::from_hash(hash);
+            prerequisite.fetch_change_id_if_missing(&repo)?;
+            prerequisite.fetch_title_if_missing(&repo)?;
+
+            match fix::is_commit_already_applied(&repo, &prerequisite, &base)? {
+                Some(applied_by) => debug!("Prerequisite {} already applied ({:?})", prerequisite.hash, applied_by),
+                None => {
+                    debug!("Found prerequisite {} of {}", prerequisite.hash, entry.commit.hash);
+                    prerequisites.push(prerequisite);
+                }
+            }
+        }
+
+        report.commits.push(DependentCommit {
+            commit: entry.commit.clone(),
+            prerequisites,
+        });
+    }
+
+    let object_format = repo.object_format();
+    let comment = |dependent: &CommitInfo| format!("# prerequisite of {}", object_format.abbreviate(&dependent.hash));
+
+    if args.in_place {
+        // Each prerequisite goes before the first listed commit descending from it, at the
+        // latest the commit needing it, so the file stays oldest first
+        let mut slots: Vec<Vec<CommitEntry>> = vec![Vec::new(); entries.len()];
+        let mut inserted = 0;
+        for (i, dependent) in report.commits.iter().enumerate() {
+            for prerequisite in &dependent.prerequisites {
+                let mut slot = slots.iter().position(|slot| slot.iter().any(|e| e.commit.hash == prerequisite.hash));
+                if slot.is_none() {
+                    inserted += 1;
+                    for (j, entry) in entries[..i].iter().enumerate() {
+                        if repo.is_ancestor(&prerequisite.hash, &entry.commit.hash)? {
+                            slot = Some(j);
+                            break;
+                        }
+                    }
+                }
+                fix::add_output_entry(&mut slots[slot.unwrap_or(i)], prerequisite.clone(), comment(&dependent.commit));
+            }
+        }
+
+        let mut updated: Vec<CommitEntry> = Vec::new();
+        for (entry, mut slot) in entries.into_iter().zip(slots) {
+            // Prerequisites of several commits can share a slot
+            if slot.len() > 1 {
+                let order = oldest_first(&repo, slot.iter().map(|e| e.commit.hash.clone()).collect(), &args.ref_branch)?;
+                slot.sort_by_key(|e| order.iter().position(|hash| *hash == e.commit.hash));
+            }
+            updated.extend(slot);
+            updated.push(entry);
+        }
+
+        CommitsParser::write_to_file(&repo, &args.commits_file, &modelines, &updated)?;
+        match format {
+            OutputFormat::Text => println!("Inserted {} prerequisites into {}", inserted, args.commits_file),
+            OutputFormat::Json => output::print_json(&report)?,
+        }
+        return Ok(());
+    }
+
+    match format {
+        OutputFormat::Text => {
+            let mut prerequisites = Vec::new();
+            for dependent in &report.commits {
+                for prerequisite in &dependent.prerequisites {
+                    fix::add_output_entry(&mut prerequisites, prerequisite.clone(), comment(&dependent.commit));
+                }
+            }
+            fix::output_commits_file(&prerequisites)?;
+        }
+        OutputFormat::Json => output::print_json(&report)?,
+    }
+
+    Ok(())
+}
+
+/// Order upstream commits oldest first, looking only at the part of the branch holding them
+fn oldest_first(repo: &Repository, hashes: Vec<String>, ref_branch: &str) -> Result<Vec<String>> {
+    if hashes.len() < 2 {
+        return Ok(hashes);
+    }
+    Ok(sort::sort_commits_topologically(repo, hashes, &[ref_branch.to_string()])?.hashes)
+}
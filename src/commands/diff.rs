@@ -0,0 +1,264 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::fs;
+use std::process::Command;
+use log::debug;
+use serde::Serialize;
+use crate::error::{Error, Result};
+use crate::commands::fix::{self, FoundBy, FoundOriginal};
+use crate::commands::sort;
+use crate::utils::backend::GitBackend;
+use crate::utils::commits::CommitInfo;
+use crate::utils::output::{self, OutputFormat};
+use crate::utils::repo::Repository;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Backported commit to compare with its upstream original(s)
+    #[arg(required_unless_present = "range", conflicts_with = "range")]
+    pub commit: Option<String>,
+
+    /// Compare every commit in a range instead, e.g. base..HEAD
+    #[arg(long = "range")]
+    pub range: Option<String>,
+
+    /// Upstream branch the commits were backported from
+    #[arg(long = "ref", required = true)]
+    pub ref_branch: String,
+
+    /// Only report whether each commit is a clean pick or was modified, without the interdiffs
+    #[arg(long = "summary")]
+    pub summary: bool,
+}
+
+/// JSON document printed by the diff command
+#[derive(Serialize)]
+struct DiffReport {
+    commits: Vec<CommitDiff>,
+}
+
+/// A backported commit compared with its upstream original(s)
+#[derive(Serialize)]
+struct CommitDiff {
+    #[serde(flatten)]
+    commit: CommitInfo,
+    originals: Vec<FoundOriginal>,
+    state: BackportState,
+    /// Differences between the original patch(es) and the backported one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interdiff: Option<String>,
+}
+
+/// How a backported commit compares to its upstream original(s)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum BackportState {
+    /// The patch is the same as upstream, apart from line numbers
+    Clean,
+    /// The patch was changed while backporting it
+    Modified,
+    /// No upstream original was found
+    NoOriginal,
+}
+
+impl BackportState {
+    /// Name of the state as used in the JSON output
+    fn name(self) -> &'static str {
+        match self {
+            BackportState::Clean => "clean",
+            BackportState::Modified => "modified",
+            BackportState::NoOriginal => "no-original",
+        }
+    }
+}
+
+/// Handle the diff command - show how backported commits differ from their upstream originals
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    let repo = Repository::open()?;
+
+    let commits = match (&args.commit, &args.range) {
+        (_, Some(range)) => {
+            let (base, tip) = range.split_once("..")
+                .ok_or_else(|| Error::InvalidRange(range.clone()))?;
+            let tip = if tip.is_empty() { "HEAD" } else { tip };
+            fix::get_commits_in_range(&repo, base, tip)?
+        }
+        (Some(commit), None) => {
+            let hash = repo.resolve_commit(commit)?;
+            vec![CommitInfo::from_hash(hash)]
+        }
+        (None, None) => unreachable!("clap requires a commit or --range"),
+    };
+
+    let mut report = DiffReport {
+        commits: Vec::new(),
+    };
+    for mut commit in commits {
+        commit.fetch_change_id_if_missing(&repo)?;
+        commit.fetch_title_if_missing(&repo)?;
+
+        let mut originals = fix::find_all_original_commits(&repo, &commit, &args.ref_branch)?;
+        for hash in fix::find_cherry_picked_originals(&repo, &commit.hash, &args.ref_branch)? {
+            if !originals.iter().any(|original| original.hash == hash) {
+                originals.push(FoundOriginal {
+                    hash,
+                    found_by: FoundBy::CherryPick,
+                });
+            }
+        }
+        // Squashed originals are compared in the order they were applied upstream
+        if originals.len() > 1 {
+            let hashes = originals.iter().map(|original| original.hash.clone()).collect();
+            let order = sort::sort_commits_topologically(&repo, hashes, std::slice::from_ref(&args.ref_branch))?.hashes;
+            originals.sort_by_key(|original| order.iter().position(|hash| *hash == original.hash));
+        }
+
+        if originals.is_empty() {
+            debug!("No original found for {}", commit.hash);
+            report.commits.push(CommitDiff {
+                commit,
+                originals,
+                state: BackportState::NoOriginal,
+                interdiff: None,
+            });
+            continue;
+        }
+
+        let original_patch = match originals.as_slice() {
+            [original] => normalize_patch(&repo.patch(&original.hash)?),
+            // Squashed originals are compared as the one change they make together, like the backport
+            [first, .., last] => {
+                let mut paths: Vec<String> = Vec::new();
+                for original in &originals {
+                    for path in repo.changed_files(&original.hash)? {
+                        if !paths.contains(&path) {
+                            paths.push(path);
+                        }
+                    }
+                }
+                normalize_patch(&repo.squashed_patch(&first.hash, &last.hash, &paths)?)
+            }
+            [] => unreachable!("commits without originals were reported above"),
+        };
+        let backport_patch = normalize_patch(&repo.patch(&commit.hash)?);
+
+        let (state, interdiff) = if original_patch == backport_patch {
+            (BackportState::Clean, None)
+        } else if args.summary {
+            (BackportState::Modified, None)
+        } else {
+            (BackportState::Modified, Some(interdiff(&repo, &original_patch, &backport_patch)?))
+        };
+        debug!("{} is {:?} compared to {} original(s)", commit.hash, state, originals.len());
+
+        report.commits.push(CommitDiff {
+            commit,
+            originals,
+            state,
+            interdiff,
+        });
+    }
+
+    match format {
+        OutputFormat::Text if args.summary => print_summary(&repo, &report),
+        OutputFormat::Text => print_interdiffs(&repo, &report),
+        OutputFormat::Json => output::print_json(&report)?,
+    }
+
+    Ok(())
+}
+
+/// Strip what legitimately changes when a patch is applied elsewhere: blob ids and hunk positions
+fn normalize_patch(patch: &str) -> String {
+    let mut normalized = String::new();
+    for line in patch.lines() {
+        if line.starts_with("index ") {
+            continue;
+        }
+        // The function name git prints after the line numbers moves with unrelated changes too
+        if line.starts_with("@@ ") {
+            normalized.push_str("@@\n");
+        } else {
+            normalized.push_str(line);
+            normalized.push('\n');
+        }
+    }
+    normalized
+}
+
+/// Diff two normalized patches, returning only the hunks
+fn interdiff(repo: &Repository, original: &str, backport: &str) -> Result<String> {
+    // Removed when dropped, also on errors, and private to this run
+    let dir = tempfile::Builder::new()
+        .prefix("bp-diff-")
+        .tempdir_in(repo.git_path("")?)?;
+    let original_path = dir.path().join("original.patch");
+    let backport_path = dir.path().join("backport.patch");
+    fs::write(&original_path, original)?;
+    fs::write(&backport_path, backport)?;
+
+    let args = ["diff", "--no-index", "--no-color", "--no-ext-diff", "--"];
+    debug!("Running command: git {} {} {}", args.join(" "), original_path.display(), backport_path.display());
+    let output = Command::new("git")
+        .args(args)
+        .arg(&original_path)
+        .arg(&backport_path)
+        .output()?;
+    dir.close()?;
+
+    // git diff --no-index exits with 1 when the files differ
+    if !matches!(output.status.code(), Some(0 | 1)) {
+        return Err(Error::git_failed(&args, &output));
+    }
+
+    // The file header names the temporary files, which means nothing to the reader
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip_while(|line| !line.starts_with("@@"))
+        .map(|line| format!("{}\n", line))
+        .collect())
+}
+
+/// Print the interdiff of every commit, headed by the commit and its originals
+fn print_interdiffs(repo: &Repository, report: &DiffReport) {
+    let object_format = repo.object_format();
+
+    for (i, diff) in report.commits.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("commit {} {}", diff.commit.hash, diff.commit.title.as_deref().unwrap_or(""));
+        for original in &diff.originals {
+            println!("original {} ({})", object_format.abbreviate(&original.hash), original.found_by.name());
+        }
+
+        match &diff.interdiff {
+            Some(interdiff) => {
+                println!("--- original");
+                println!("+++ backport");
+                print!("{}", interdiff);
+            }
+            None if diff.state == BackportState::Clean => println!("Clean pick, no differences"),
+            None => println!("No upstream original found"),
+        }
+    }
+}
+
+/// Print one line per commit with its state and originals
+fn print_summary(repo: &Repository, report: &DiffReport) {
+    let object_format = repo.object_format();
+    let width = object_format.display_abbrev_len();
+
+    for diff in &report.commits {
+        let originals: Vec<&str> = diff.originals.iter()
+            .map(|original| object_format.abbreviate(&original.hash))
+            .collect();
+        let originals = if originals.is_empty() { "-".to_string() } else { originals.join(",") };
+        println!("{}  {:<11}  {:<width$}  {}", object_format.abbreviate(&diff.commit.hash), diff.state.name(),
+                 originals, diff.commit.title.as_deref().unwrap_or(""));
+    }
+}
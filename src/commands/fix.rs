@@ -4,9 +4,15 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 
-use std::process::Command;
 use log::{debug, warn};
-use crate::utils::commits::CommitInfo;
+use serde::Serialize;
+use std::collections::HashSet;
+use crate::error::Result;
+use crate::utils::backend::GitBackend;
+use crate::utils::commits::{CommitEntry, CommitInfo};
+use crate::utils::output::{self, OutputFormat};
+use crate::utils::hash::ObjectFormat;
+use crate::utils::repo::Repository;
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -17,133 +23,314 @@ pub struct Args {
     /// Reference branch to search for fixes
     #[arg(long = "ref", required = true)]
     pub ref_branch: String,
+
+    /// Maximum length of a fix chain to follow (1 only finds direct fixes, default: unlimited)
+    #[arg(long = "depth", value_parser = clap::value_parser!(u32).range(1..))]
+    pub depth: Option<u32>,
+
+    /// Also output upstream reverts of backported commits
+    #[arg(long = "include-reverts")]
+    pub include_reverts: bool,
+}
+
+/// Everything the fix command found, in the order commits appear in base..HEAD
+#[derive(Serialize)]
+struct FixReport {
+    commits: Vec<BackportedCommit>,
+}
+
+/// A commit in base..HEAD and what was found for it on the reference branch
+#[derive(Serialize)]
+struct BackportedCommit {
+    #[serde(flatten)]
+    commit: CommitInfo,
+    originals: Vec<OriginalCommit>,
+}
+
+/// An original commit on the reference branch and the commits related to it
+#[derive(Serialize)]
+struct OriginalCommit {
+    hash: String,
+    found_by: FoundBy,
+    fixes: Vec<FoundFix>,
+    reverts: Vec<FoundRevert>,
+    /// Commits mentioning the original commit without being marked as a fix
+    references: Vec<CommitInfo>,
 }
 
 /// Handle the fix command - find fixes for commits on a reference branch
-pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    let repo = Repository::open()?;
+
     // Get commits in range base..HEAD
-    let commits_in_range = get_commits_in_range(&args.base, "HEAD")?;
+    let commits_in_range = get_commits_in_range(&repo, &args.base, "HEAD")?;
 
     if commits_in_range.is_empty() {
         debug!("No commits found in range {}..HEAD", args.base);
-        return Ok(());
+    } else {
+        debug!("Found {} commits in range {}..HEAD", commits_in_range.len(), args.base);
     }
 
-    debug!("Found {} commits in range {}..HEAD", commits_in_range.len(), args.base);
-
     // Process each commit in the range
-    let mut fix_commits = Vec::new();
+    let mut report = FixReport {
+        commits: Vec::new(),
+    };
 
     for mut commit in commits_in_range {
         // Enrich commit info
-        commit.fetch_change_id_if_missing()?;
-        commit.fetch_title_if_missing()?;
+        commit.fetch_change_id_if_missing(&repo)?;
+        commit.fetch_title_if_missing(&repo)?;
 
         debug!("Processing commit: {} {:?} {:?}",
                commit.hash, commit.change_id, commit.title);
 
         // Find all original commits on ref branch
-        let original_commits = find_all_original_commits(&commit, &args.ref_branch)?;
+        let original_commits = find_all_original_commits(&repo, &commit, &args.ref_branch)?;
 
         if original_commits.is_empty() {
             debug!("Could not find any original commits for {} on {}", commit.hash, args.ref_branch);
         } else {
             debug!("Found {} original commit(s) for {}: {:?}", original_commits.len(), commit.hash, original_commits);
+        }
 
-            // Search for fixes for each original commit
-            for original_commit in &original_commits {
-                debug!("Processing original commit: {}", original_commit);
+        let mut originals = Vec::new();
 
-                // Search for fixes on ref branch
-                let fixes = find_fixes_for_commit(original_commit, &args.ref_branch, &args.base)?;
+        // Search for fixes for each original commit
+        for FoundOriginal { hash: original_commit, found_by } in original_commits {
+            debug!("Processing original commit: {}", original_commit);
 
-                if !fixes.is_empty() {
-                    debug!("Found {} fix(es) for {}: {:?}", fixes.len(), original_commit, fixes);
-                    for fix_commit in fixes {
-                        fix_commits.push(fix_commit);
-                    }
+            // Search for fixes on ref branch, following fixes of fixes
+            let fixes = find_fix_closure(&repo, &original_commit, &args.ref_branch, &args.base, args.depth)?;
+            debug!("Found {} fix(es) for {}", fixes.len(), original_commit);
+
+            // Search for upstream reverts of the original commit
+            let reverts = find_reverts_for_commit(&repo, &original_commit, &args.ref_branch, &args.base)?;
+
+            // Check for references that are not explicit fixes
+            let references = find_references_for_commit(&repo, &original_commit, &args.ref_branch)?;
+            debug!("Found {} references for {}: {:?}", references.len(), original_commit, references);
+            let mut non_fix_references = Vec::new();
+            for reference in references {
+                if reverts.iter().any(|revert| revert.commit.hash == reference) {
+                    debug!("Reference {} is a revert, already reported", reference);
+                    continue;
                 }
 
-                // Check for references that are not explicit fixes
-                let references = find_references_for_commit(original_commit, &args.ref_branch)?;
-                debug!("Found {} references for {}: {:?}", references.len(), original_commit, references);
-                for reference in references {
-                    debug!("Checking if reference {} is an explicit fix for {}", reference, original_commit);
-                    if !is_explicit_fix(&reference, original_commit)? {
-                        if let Some(ref_title) = get_commit_title(&reference)? {
-                            warn!("Commit {} references {} but is not marked as a fix: {}",
-                                  reference, original_commit, ref_title);
-                        } else {
-                            warn!("Commit {} references {} but is not marked as a fix",
-                                  reference, original_commit);
-                        }
+                debug!("Checking if reference {} is an explicit fix for {}", reference, original_commit);
+                if !is_explicit_fix(&repo, &reference, &original_commit, &args.ref_branch)? {
+                    let ref_title = repo.title(&reference)?;
+                    if let Some(ref_title) = &ref_title {
+                        warn!("Commit {} references {} but is not marked as a fix: {}",
+                              reference, original_commit, ref_title);
                     } else {
-                        debug!("Reference {} is an explicit fix, skipping warning", reference);
+                        warn!("Commit {} references {} but is not marked as a fix",
+                              reference, original_commit);
                     }
+                    let mut reference_info = CommitInfo::from_hash(reference);
+                    reference_info.title = ref_title;
+                    non_fix_references.push(reference_info);
+                } else {
+                    debug!("Reference {} is an explicit fix, skipping warning", reference);
                 }
             }
+
+            originals.push(OriginalCommit {
+                hash: original_commit,
+                found_by,
+                fixes,
+                reverts,
+                references: non_fix_references,
+            });
         }
+
+        report.commits.push(BackportedCommit {
+            commit,
+            originals,
+        });
     }
 
-    // Remove duplicates based on hash
-    fix_commits.sort_by(|a, b| a.hash.cmp(&b.hash));
-    fix_commits.dedup_by(|a, b| a.hash == b.hash);
+    report_reverts(&report, args.include_reverts, repo.object_format());
+    report_title_mismatches(&report, repo.object_format());
 
-    debug!("Final fix commits count after deduplication: {}", fix_commits.len());
+    match format {
+        OutputFormat::Text => {
+            let fix_commits = collect_output_entries(&report, args.include_reverts, repo.object_format());
+            debug!("Final fix commits count after deduplication: {}", fix_commits.len());
 
-    // Generate commits file format and output to stdout
-    output_commits_file(&fix_commits)?;
+            // Generate commits file format and output to stdout
+            output_commits_file(&fix_commits)?;
+        }
+        OutputFormat::Json => output::print_json(&report)?,
+    }
 
     Ok(())
 }
 
-/// Get commits in the specified range
-fn get_commits_in_range(base: &str, head: &str) -> Result<Vec<CommitInfo>, Box<dyn std::error::Error>> {
-    let range = format!("{}..{}", base, head);
-    let args = ["rev-list", "--reverse", &range];
-    debug!("Running command: git {}", args.join(" "));
-    let output = Command::new("git")
-        .args(args)
-        .output()?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("git rev-list failed: {}", stderr).into());
+/// Build the commits file entries for fixes (and reverts if requested) that still need to be picked
+fn collect_output_entries(report: &FixReport, include_reverts: bool, object_format: ObjectFormat) -> Vec<CommitEntry> {
+    let mut entries = Vec::new();
+
+    for backported in &report.commits {
+        for original in &backported.originals {
+            for fix in original.fixes.iter().filter(|fix| !fix.applied) {
+                let comment = format_fix_chain(&fix.chain, &backported.commit.hash, object_format);
+                add_output_entry(&mut entries, fix.commit.clone(), comment);
+            }
+
+            if include_reverts {
+                for revert in original.reverts.iter().filter(|revert| !revert.applied) {
+                    let comment = format!("# revert of {} (backported as {})",
+                                          object_format.abbreviate(&original.hash), object_format.abbreviate(&backported.commit.hash));
+                    add_output_entry(&mut entries, revert.commit.clone(), comment);
+                }
+            }
+        }
     }
 
-    let commits_text = String::from_utf8_lossy(&output.stdout);
-    let mut commits = Vec::new();
+    // Fixes reached through several chains were merged above, keep a stable order
+    entries.sort_by(|a, b| a.commit.hash.cmp(&b.commit.hash));
+    entries
+}
 
-    for line in commits_text.lines() {
-        let line = line.trim();
-        if !line.is_empty() {
-            commits.push(CommitInfo::from_hash(line.to_string()));
+/// Print reverts that are not applied yet to stderr
+///
+/// Shipping a commit that upstream reverted is worse than missing a fix,
+/// so reverts are always reported regardless of the log level.
+fn report_reverts(report: &FixReport, include_reverts: bool, object_format: ObjectFormat) {
+    let mut lines = Vec::new();
+    for backported in &report.commits {
+        for original in &backported.originals {
+            for revert in original.reverts.iter().filter(|revert| !revert.applied) {
+                lines.push(format!("  {} {} (reverts {}, backported as {})",
+                                   object_format.abbreviate(&revert.commit.hash),
+                                   revert.commit.title.as_deref().unwrap_or(""),
+                                   object_format.abbreviate(&original.hash),
+                                   object_format.abbreviate(&backported.commit.hash)));
+            }
         }
     }
 
+    if lines.is_empty() {
+        return;
+    }
+
+    eprintln!("WARNING: {} backported commit(s) were reverted upstream:", lines.len());
+    for line in lines {
+        eprintln!("{}", line);
+    }
+    if !include_reverts {
+        eprintln!("Use --include-reverts to add them to the output");
+    }
+}
+
+/// Print fixes whose `Fixes:` trailer quotes a different title than the commit they fix to stderr
+///
+/// The hash still names the fixed commit, but a wrong title hints at a
+/// trailer that was copied from elsewhere or points at the wrong commit.
+fn report_title_mismatches(report: &FixReport, object_format: ObjectFormat) {
+    let mut lines = Vec::new();
+    for backported in &report.commits {
+        for original in &backported.originals {
+            for fix in &original.fixes {
+                let Some(quoted) = &fix.mismatched_title else {
+                    continue;
+                };
+                let fixed = &fix.chain[fix.chain.len() - 2];
+                let line = format!("  {} {} (quotes \"{}\" for {})",
+                                   object_format.abbreviate(&fix.commit.hash),
+                                   fix.commit.title.as_deref().unwrap_or(""),
+                                   quoted,
+                                   object_format.abbreviate(fixed));
+                if !lines.contains(&line) {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+
+    if lines.is_empty() {
+        return;
+    }
+
+    eprintln!("WARNING: {} fix(es) quote a title that does not match the commit they fix:", lines.len());
+    for line in lines {
+        eprintln!("{}", line);
+    }
+}
+
+/// Get commits in the specified range
+pub fn get_commits_in_range(repo: &impl GitBackend, base: &str, head: &str) -> Result<Vec<CommitInfo>> {
+    let mut commits: Vec<CommitInfo> = repo.range(base, head)?
+        .iter()
+        .cloned()
+        .map(CommitInfo::from_hash)
+        .collect();
+
+    // Oldest first, like `git rev-list --reverse`
+    commits.reverse();
+
     Ok(commits)
 }
 
+/// How the upstream original of a backported commit was found
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FoundBy {
+    /// The original has the same Change-Id
+    ChangeId,
+    /// The original has a Change-Id listed in a `Was-Change-Id:` trailer
+    WasChangeId,
+    /// The backported commit records the original in a "cherry picked from commit" line
+    CherryPick,
+    /// The original has the same `git patch-id --stable`
+    PatchId,
+}
+
+impl FoundBy {
+    /// Name of the method as used in the JSON output
+    pub fn name(self) -> &'static str {
+        match self {
+            FoundBy::ChangeId => "change-id",
+            FoundBy::WasChangeId => "was-change-id",
+            FoundBy::CherryPick => "cherry-pick",
+            FoundBy::PatchId => "patch-id",
+        }
+    }
+}
+
+/// An original commit on the reference branch and how it was found
+#[derive(Debug, Serialize)]
+pub struct FoundOriginal {
+    pub hash: String,
+    pub found_by: FoundBy,
+}
+
 /// Find all original commits on ref branch based on change-id and was-change-ids
-fn find_all_original_commits(commit: &CommitInfo, ref_branch: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let mut found_originals = Vec::new();
+pub fn find_all_original_commits(repo: &impl GitBackend, commit: &CommitInfo, ref_branch: &str) -> Result<Vec<FoundOriginal>> {
+    let mut found_originals: Vec<FoundOriginal> = Vec::new();
 
     // Try to find by change-id first
     if let Some(change_id) = &commit.change_id {
-        if let Some(original) = find_commit_by_change_id(change_id, ref_branch)? {
+        if let Some(original) = find_commit_by_change_id(repo, change_id, ref_branch)? {
             debug!("Found original commit using change-id: {}", change_id);
-            found_originals.push(original);
+            found_originals.push(FoundOriginal {
+                hash: original,
+                found_by: FoundBy::ChangeId,
+            });
         }
     }
 
     // Always try to find by was-change-ids - each represents a separate original commit
-    let was_change_ids = get_was_change_ids(&commit.hash)?;
+    let was_change_ids = get_was_change_ids(repo, &commit.hash)?;
     for was_change_id in was_change_ids {
-        if let Some(original) = find_commit_by_change_id(&was_change_id, ref_branch)? {
+        if let Some(original) = find_commit_by_change_id(repo, &was_change_id, ref_branch)? {
             debug!("Found original commit using was-change-id: {}", was_change_id);
             // Check for duplicates before adding
-            if !found_originals.contains(&original) {
-                found_originals.push(original);
+            if !found_originals.iter().any(|found| found.hash == original) {
+                found_originals.push(FoundOriginal {
+                    hash: original,
+                    found_by: FoundBy::WasChangeId,
+                });
             } else {
                 debug!("Duplicate original commit {} found, skipping", original);
             }
@@ -153,265 +340,394 @@ fn find_all_original_commits(commit: &CommitInfo, ref_branch: &str) -> Result<Ve
     Ok(found_originals)
 }
 
-/// Find commit by change-id on specified branch
-fn find_commit_by_change_id(change_id: &str, ref_branch: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    let grep_pattern = format!("Change-Id: {}", change_id);
-    let args = ["log", "--format=%H", "--grep", &grep_pattern, ref_branch];
-    debug!("Running command: git {}", args.join(" "));
-    let output = Command::new("git")
-        .args(args)
-        .output()?;
-
-    if !output.status.success() {
-        return Ok(None);
-    }
-
-    let commits_text = String::from_utf8_lossy(&output.stdout);
-    for line in commits_text.lines() {
-        let line = line.trim();
-        if !line.is_empty() {
-            return Ok(Some(line.to_string()));
+/// Find the originals a commit picked with -x names in its "cherry picked from commit" lines
+///
+/// Only commits on the reference branch count, so picks from other branches are ignored.
+pub fn find_cherry_picked_originals(repo: &impl GitBackend, commit_hash: &str, ref_branch: &str) -> Result<Vec<String>> {
+    let upstream = repo.history(ref_branch)?;
+    let cherry_picked_from = repo.metadata(commit_hash)?
+        .map(|metadata| metadata.cherry_picked_from)
+        .unwrap_or_default();
+
+    let mut originals = Vec::new();
+    for hash in cherry_picked_from {
+        if let Some(hash) = repo.resolve(&hash)?.filter(|hash| upstream.contains(hash)) {
+            originals.push(hash);
         }
     }
+    Ok(originals)
+}
 
-    Ok(None)
+/// Find commit by change-id on specified branch
+fn find_commit_by_change_id(repo: &impl GitBackend, change_id: &str, ref_branch: &str) -> Result<Option<String>> {
+    Ok(repo.trailer_index(ref_branch)?.find_by_change_id(change_id))
 }
 
 /// Get all was-change-ids from commit message
-fn get_was_change_ids(commit_hash: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let args = ["log", "--format=%B", "-n", "1", commit_hash];
-    debug!("Running command: git {}", args.join(" "));
-    let output = Command::new("git")
-        .args(args)
-        .output()?;
-
-    if !output.status.success() {
-        return Ok(Vec::new());
-    }
-
-    let body = String::from_utf8_lossy(&output.stdout);
-    let mut was_change_ids = Vec::new();
-
-    for line in body.lines() {
-        if line.starts_with("Was-Change-Id: I") {
-            if let Some(was_change_id) = line.strip_prefix("Was-Change-Id: ") {
-                was_change_ids.push(was_change_id.trim().to_string());
-            }
-        }
-    }
+fn get_was_change_ids(repo: &impl GitBackend, commit_hash: &str) -> Result<Vec<String>> {
+    Ok(repo.metadata(commit_hash)?
+        .map(|metadata| metadata.was_change_ids)
+        .unwrap_or_default())
+}
 
-    Ok(was_change_ids)
+/// How a commit was found to be applied on the current branch already
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AppliedBy {
+    /// The commit itself is an ancestor of HEAD
+    Ancestor,
+    /// A commit in base..HEAD has the same Change-Id
+    ChangeId,
+    /// A commit in base..HEAD records it in a "cherry picked from commit" line
+    CherryPick,
+    /// A commit in base..HEAD has the same `git patch-id --stable`
+    PatchId,
+    /// A commit in base..HEAD has the same title and touches the same files
+    TitleAndFiles,
 }
 
 /// Check if a commit already exists on current branch through various means:
 /// 1. Direct hash ancestry check
 /// 2. Same Change-Id check  
 /// 3. Cherry-pick trace check
-fn is_commit_already_applied(commit_info: &CommitInfo, base: &str) -> Result<bool, Box<dyn std::error::Error>> {
+/// 4. Same patch-id check, for commits applied with `git am` or without `-x`
+/// 5. Same title and same changed files, for commits that needed conflict resolution
+pub fn is_commit_already_applied(repo: &impl GitBackend, commit_info: &CommitInfo, base: &str) -> Result<Option<AppliedBy>> {
     // 1. Check direct ancestry
-    let args = ["merge-base", "--is-ancestor", &commit_info.hash, "HEAD"];
-    debug!("Running command: git {}", args.join(" "));
-    let output = Command::new("git")
-        .args(args)
-        .output()?;
-    
-    if output.status.success() {
+    if repo.is_ancestor(&commit_info.hash, "HEAD")? {
         debug!("Commit {} is already an ancestor of HEAD", commit_info.hash);
-        return Ok(true);
+        return Ok(Some(AppliedBy::Ancestor));
     }
 
+    let backported = repo.range_metadata(base, "HEAD")?;
+
     // 2. Check by Change-Id if available
     if let Some(change_id) = &commit_info.change_id {
-        let range = format!("{}..HEAD", base);
-        let grep_pattern = format!("Change-Id: {}", change_id);
-        let args = ["log", "--format=%H", "--grep", &grep_pattern, &range];
-        debug!("Running command: git {}", args.join(" "));
-        let output = Command::new("git")
-            .args(args)
-            .output()?;
-        
-        if output.status.success() {
-            let commits_text = String::from_utf8_lossy(&output.stdout);
-            if !commits_text.trim().is_empty() {
-                debug!("Commit with Change-Id {} already exists on current branch", change_id);
-                return Ok(true);
-            }
+        if backported.iter().any(|commit| commit.change_id.as_ref() == Some(change_id)) {
+            debug!("Commit with Change-Id {} already exists on current branch", change_id);
+            return Ok(Some(AppliedBy::ChangeId));
         }
     }
 
-    // 3. Check cherry-pick records
-    let short_hash = &commit_info.hash[..std::cmp::min(7, commit_info.hash.len())];
-    let range = format!("{}..HEAD", base);
-    
-    // Search for cherry-pick pattern with both short and long hash
-    for hash_to_check in [commit_info.hash.as_str(), short_hash] {
-        let grep_pattern = format!("cherry picked from commit {}", hash_to_check);
-        let args = ["log", "--format=%H", "--grep", &grep_pattern, &range];
-        debug!("Running command: git {}", args.join(" "));
-        let output = Command::new("git")
-            .args(args)
-            .output()?;
-        
-        if output.status.success() {
-            let commits_text = String::from_utf8_lossy(&output.stdout);
-            if !commits_text.trim().is_empty() {
-                debug!("Commit {} was cherry-picked to current branch", commit_info.hash);
-                return Ok(true);
-            }
+    // 3. Check cherry-pick records, which may use the full or an abbreviated hash
+    let object_format = repo.object_format();
+    if backported.iter().any(|commit| commit.cherry_picked_from.iter().any(|hash| object_format.hashes_match(hash, &commit_info.hash))) {
+        debug!("Commit {} was cherry-picked to current branch", commit_info.hash);
+        return Ok(Some(AppliedBy::CherryPick));
+    }
+
+    // 4. Check for a commit introducing the same change
+    if let Some(patch_id) = repo.patch_id(&commit_info.hash)? {
+        if let Some(applied_as) = repo.patch_ids(base, "HEAD")?.get(&patch_id) {
+            debug!("Commit {} has the same patch-id as {}", commit_info.hash, applied_as);
+            return Ok(Some(AppliedBy::PatchId));
         }
     }
 
-    Ok(false)
+    // 5. Fall back to the same title touching the same files
+    let title = match &commit_info.title {
+        Some(title) => Some(title.clone()),
+        None => repo.title(&commit_info.hash)?,
+    };
+    let Some(title) = title else {
+        return Ok(None);
+    };
+
+    let mut files = None;
+    for commit in backported.iter().filter(|commit| commit.title.as_ref() == Some(&title)) {
+        if files.is_none() {
+            files = Some(repo.changed_files(&commit_info.hash)?);
+        }
+        let files = files.as_ref().expect("files were just listed");
+        if !files.is_empty() && *files == repo.changed_files(&commit.hash)? {
+            debug!("Commit {} has the same title and changed files as {}", commit_info.hash, commit.hash);
+            return Ok(Some(AppliedBy::TitleAndFiles));
+        }
+    }
+
+    Ok(None)
+}
+
+/// A commit with a `Fixes:` trailer naming another commit
+struct DirectFix {
+    commit: CommitInfo,
+    /// Title quoted in the trailer, when it is not the title of the fixed commit
+    mismatched_title: Option<String>,
 }
 
 /// Find commits that fix the given commit
-fn find_fixes_for_commit(original_commit: &str, ref_branch: &str, base: &str) -> Result<Vec<CommitInfo>, Box<dyn std::error::Error>> {
+fn find_fixes_for_commit(repo: &impl GitBackend, original_commit: &str, ref_branch: &str) -> Result<Vec<DirectFix>> {
     debug!("Searching for fixes for commit: {} on branch: {}", original_commit, ref_branch);
 
-    // Search for commits that contain "Fixes: <commit_hash>" pattern
-    // Only search commits that come after the original commit (since fixes can't appear before)
-    let range = format!("{}..{}", original_commit, ref_branch);
+    // Look up commits with a "Fixes: <commit_hash>" trailer, the index only returns
+    // commits that come after the original commit (since fixes can't appear before)
+    let mut fix_commits = Vec::new();
 
-    // Use short hash since it will match both short and long hash patterns in commit messages
-    let short_hash = &original_commit[..std::cmp::min(7, original_commit.len())];
+    for fix in repo.trailer_index(ref_branch)?.fixes_of(original_commit) {
+        let mut commit_info = CommitInfo::from_hash(fix.hash);
+        commit_info.fetch_change_id_if_missing(repo)?;
+        commit_info.fetch_title_if_missing(repo)?;
+        debug!("Found fix commit: {} for {}", commit_info.hash, original_commit);
+        fix_commits.push(DirectFix {
+            commit: commit_info,
+            mismatched_title: fix.mismatched_title,
+        });
+    }
 
-    debug!("Searching for fix pattern 'Fixes: {}' in range: {}", short_hash, range);
+    debug!("Found {} fix commits for {}", fix_commits.len(), original_commit);
+    Ok(fix_commits)
+}
 
-    let grep_pattern = format!("Fixes: {}", short_hash);
-    let args = [
-        "log",
-        "--format=%H",
-        "--grep", &grep_pattern,
-        &range
-    ];
-    debug!("Running command: git {}", args.join(" "));
-    let output = Command::new("git")
-        .args(args)
-        .output()?;
+/// A fix found on the reference branch together with how it was reached
+#[derive(Serialize)]
+struct FoundFix {
+    #[serde(flatten)]
+    commit: CommitInfo,
+    /// Commits from the original commit down to this fix
+    chain: Vec<String>,
+    /// Whether the fix is already applied on the current branch
+    applied: bool,
+    /// How the fix was found to be applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    applied_by: Option<AppliedBy>,
+    /// Title quoted in the `Fixes:` trailer, when it is not the title of the fixed commit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mismatched_title: Option<String>,
+}
 
-    let mut fix_commits = Vec::new();
+/// A revert of an original commit found on the reference branch
+#[derive(Serialize)]
+struct FoundRevert {
+    #[serde(flatten)]
+    commit: CommitInfo,
+    /// Whether the revert is already applied on the current branch
+    applied: bool,
+    /// How the revert was found to be applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    applied_by: Option<AppliedBy>,
+}
 
-    if output.status.success() {
-        let commits_text = String::from_utf8_lossy(&output.stdout);
-        for line in commits_text.lines() {
-            let line = line.trim();
-            if !line.is_empty() {
-                let mut commit_info = CommitInfo::from_hash(line.to_string());
-                commit_info.fetch_change_id_if_missing()?;
-                commit_info.fetch_title_if_missing()?;
-                
-                // Check if this fix commit is already applied on current branch
-                if is_commit_already_applied(&commit_info, base)? {
-                    debug!("Fix commit {} already applied on current branch, skipping", line);
-                    continue;
-                }
-                
-                fix_commits.push(commit_info);
-                debug!("Found fix commit: {} for {}", line, original_commit);
+/// Find fixes for the given commit, then fixes of those fixes, and so on
+///
+/// Each returned fix comes with the chain of commits that led to it, starting
+/// with `original_commit` and ending with the fix itself. Fixes that are already
+/// applied on the current branch are marked as such, and their own fixes are
+/// still followed.
+/// The walk stops after `max_depth` steps when a limit is given.
+fn find_fix_closure(
+    repo: &impl GitBackend,
+    original_commit: &str,
+    ref_branch: &str,
+    base: &str,
+    max_depth: Option<u32>,
+) -> Result<Vec<FoundFix>> {
+    let mut result = Vec::new();
+    let mut visited = HashSet::new();
+    visited.insert(original_commit.to_string());
+
+    let mut queue = std::collections::VecDeque::new();
+    queue.push_back(vec![original_commit.to_string()]);
+
+    while let Some(chain) = queue.pop_front() {
+        if max_depth.is_some_and(|max_depth| chain.len() > max_depth as usize) {
+            continue;
+        }
+
+        let current = chain.last().expect("fix chain is never empty");
+        for DirectFix { commit: fix_commit, mismatched_title } in find_fixes_for_commit(repo, current, ref_branch)? {
+            if !visited.insert(fix_commit.hash.clone()) {
+                debug!("Fix commit {} already visited, skipping", fix_commit.hash);
+                continue;
+            }
+
+            let mut fix_chain = chain.clone();
+            fix_chain.push(fix_commit.hash.clone());
+            queue.push_back(fix_chain.clone());
+
+            // Check if this fix commit is already applied on current branch
+            let applied_by = is_commit_already_applied(repo, &fix_commit, base)?;
+            if let Some(applied_by) = applied_by {
+                debug!("Fix commit {} already applied on current branch ({:?})", fix_commit.hash, applied_by);
+            } else {
+                debug!("Found fix commit: {} for {}", fix_commit.hash, current);
             }
+
+            result.push(FoundFix {
+                commit: fix_commit,
+                chain: fix_chain,
+                applied: applied_by.is_some(),
+                applied_by,
+                mismatched_title,
+            });
         }
     }
 
-    debug!("Found {} fix commits for {}", fix_commits.len(), original_commit);
-    Ok(fix_commits)
-}/// Find commits that reference the given commit (but may not be explicit fixes)
-fn find_references_for_commit(original_commit: &str, ref_branch: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    // Use short hash since it will match both short and long hash patterns in commit messages
-    let short_hash = &original_commit[..std::cmp::min(7, original_commit.len())];
-
-    debug!("Searching for references using short hash: {}", short_hash);
-
-    // Only search commits that come after the original commit (since references can't appear before)
-    let range = format!("{}..{}", original_commit, ref_branch);
-
-    let args = ["log", "--format=%H", "--grep", short_hash, &range];
-    debug!("Running command: git {}", args.join(" "));
-    let output = Command::new("git")
-        .args(args)
-        .output()?;
-
-    let mut all_references = Vec::new();
-
-    if output.status.success() {
-        let commits_text = String::from_utf8_lossy(&output.stdout);
-        for line in commits_text.lines() {
-            let line = line.trim();
-            if !line.is_empty() {
-                all_references.push(line.to_string());
+    Ok(result)
+}
+
+/// Format a fix chain as a comment line for the commits file
+fn format_fix_chain(chain: &[String], backported_commit: &str, object_format: ObjectFormat) -> String {
+    let links: Vec<&str> = chain.iter().map(|hash| object_format.abbreviate(hash)).collect();
+    format!("# fix chain: {} (backported as {})", links.join(" -> "), object_format.abbreviate(backported_commit))
+}
+
+/// Add a commit to the output, merging the comment into an existing entry for the same commit
+pub fn add_output_entry(entries: &mut Vec<CommitEntry>, commit: CommitInfo, comment: String) {
+    match entries.iter_mut().find(|e| e.commit.hash == commit.hash) {
+        Some(entry) => {
+            if !entry.comments.contains(&comment) {
+                entry.comments.push(comment);
             }
         }
+        None => entries.push(CommitEntry::with_comments(commit, vec![comment])),
     }
-
-    Ok(all_references)
 }
 
-/// Check if a commit is an explicit fix for the original commit
-fn is_explicit_fix(commit_hash: &str, original_commit: &str) -> Result<bool, Box<dyn std::error::Error>> {
-    let args = ["log", "--format=%B", "-n", "1", commit_hash];
-    debug!("Running command: git {}", args.join(" "));
-    let output = Command::new("git")
-        .args(args)
-        .output()?;
-
-    if !output.status.success() {
-        return Ok(false);
+/// Find commits on the reference branch that revert the given commit
+///
+/// Reverts that were themselves reverted later (i.e. the commit was relanded)
+/// are not returned.
+fn find_reverts_for_commit(repo: &impl GitBackend, original_commit: &str, ref_branch: &str, base: &str) -> Result<Vec<FoundRevert>> {
+    // The index only returns commits that come after the original commit (since reverts can't appear before)
+    let mut reverts = Vec::new();
+
+    for hash in repo.trailer_index(ref_branch)?.reverts_of(original_commit) {
+        let reverted = get_reverted_commit(repo, &hash)?;
+        if !reverted.is_some_and(|reverted| repo.object_format().hashes_match(&reverted, original_commit)) {
+            debug!("Commit {} mentions {} but does not revert it", hash, original_commit);
+            continue;
+        }
+
+        if !find_reverts_for_commit(repo, &hash, ref_branch, base)?.is_empty() {
+            debug!("Revert {} was itself reverted, skipping", hash);
+            continue;
+        }
+
+        let mut commit_info = CommitInfo::from_hash(hash);
+        commit_info.fetch_change_id_if_missing(repo)?;
+        commit_info.fetch_title_if_missing(repo)?;
+        debug!("Found revert: {} of {}", commit_info.hash, original_commit);
+        let applied_by = is_commit_already_applied(repo, &commit_info, base)?;
+        reverts.push(FoundRevert {
+            commit: commit_info,
+            applied: applied_by.is_some(),
+            applied_by,
+        });
     }
 
-    let body = String::from_utf8_lossy(&output.stdout);
+    Ok(reverts)
+}
 
-    // Look for various fix patterns
-    for line in body.lines() {
-        let line = line.trim();
+/// Get the commit a revert commit reverts, from its "This reverts commit <hash>." line
+fn get_reverted_commit(repo: &impl GitBackend, commit_hash: &str) -> Result<Option<String>> {
+    Ok(repo.metadata(commit_hash)?
+        .and_then(|metadata| metadata.reverts.into_iter().next()))
+}
 
-        debug!("Checking line: '{}' for fixes of {}", line, original_commit);
+/// Find commits that reference the given commit (but may not be explicit fixes)
+fn find_references_for_commit(repo: &impl GitBackend, original_commit: &str, ref_branch: &str) -> Result<Vec<String>> {
+    debug!("Searching for references to {}", original_commit);
 
-        // Pattern: "Fixes: <commit_hash>" (may be short hash)
-        if line.starts_with("Fixes: ") {
-            if let Some(fixes_part) = line.strip_prefix("Fixes: ") {
-                // Extract the commit hash part (before any space or parenthesis)
-                let fixes_hash = fixes_part.split_whitespace().next().unwrap_or("");
+    // The index only returns commits that come after the original commit (since references can't appear before)
+    Ok(repo.trailer_index(ref_branch)?.mentions_of(original_commit))
+}
 
-                // Check if the fix hash matches the original commit (either full or partial match)
-                if original_commit.starts_with(fixes_hash) || fixes_hash.starts_with(original_commit) {
-                    debug!("Found explicit fix pattern in line: {}", line);
-                    return Ok(true);
-                }
-            }
+/// Check if a commit is an explicit fix for the original commit
+fn is_explicit_fix(repo: &impl GitBackend, commit_hash: &str, original_commit: &str, ref_branch: &str) -> Result<bool> {
+    // Pattern: "Fixes: <commit_hash>" (may be short hash)
+    Ok(repo.trailer_index(ref_branch)?
+        .fixes_of(original_commit)
+        .iter()
+        .any(|fix| fix.hash == commit_hash))
+}
+
+/// Output commits in file format to stdout
+pub fn output_commits_file(entries: &[CommitEntry]) -> Result<()> {
+    // Add vim modeline
+    println!("# vim: ft=gitbackportcommits");
+
+    // Output each commit along with the comments explaining why it was picked
+    for entry in entries {
+        for line in entry.to_lines() {
+            println!("{}", line);
         }
     }
 
-    Ok(false)
+    Ok(())
 }
 
-/// Get commit title
-fn get_commit_title(commit_hash: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    let args = ["log", "--format=%s", "-n", "1", commit_hash];
-    debug!("Running command: git {}", args.join(" "));
-    let output = Command::new("git")
-        .args(args)
-        .output()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fake::FakeRepo;
 
-    if !output.status.success() {
-        return Ok(None);
+    const CHANGE_ID_A: &str = "I1111111111111111111111111111111111111111";
+    const CHANGE_ID_B: &str = "I2222222222222222222222222222222222222222";
+    const CHANGE_ID_NEW: &str = "I3333333333333333333333333333333333333333";
+
+    fn commit_info(repo: &FakeRepo, hash: &str) -> CommitInfo {
+        let mut commit = CommitInfo::from_hash(hash.to_string());
+        commit.fetch_change_id_if_missing(repo).unwrap();
+        commit
     }
 
-    let title = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    if title.is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(title))
+    #[test]
+    fn finds_originals_by_change_id_and_was_change_ids() {
+        let mut repo = FakeRepo::new();
+        let root = repo.commit(&[], "root");
+        let a = repo.commit(&[&root], &format!("feature A\n\nChange-Id: {}", CHANGE_ID_A));
+        let b = repo.commit(&[&a], &format!("feature B\n\nChange-Id: {}", CHANGE_ID_B));
+        repo.set_ref("up", &b);
+        let backport = repo.commit(&[&root], &format!(
+            "features A and B\n\nChange-Id: {}\nWas-Change-Id: {}\nWas-Change-Id: {}\nWas-Change-Id: {}",
+            CHANGE_ID_A, CHANGE_ID_A, CHANGE_ID_B, CHANGE_ID_NEW));
+
+        let originals = find_all_original_commits(&repo, &commit_info(&repo, &backport), "up").unwrap();
+        let originals: Vec<(&str, FoundBy)> = originals.iter()
+            .map(|original| (original.hash.as_str(), original.found_by))
+            .collect();
+        assert_eq!(originals, vec![(a.as_str(), FoundBy::ChangeId), (b.as_str(), FoundBy::WasChangeId)]);
     }
-}
 
-/// Output commits in file format to stdout
-fn output_commits_file(commits: &[CommitInfo]) -> Result<(), Box<dyn std::error::Error>> {
-    // Add vim modeline
-    println!("# vim: ft=gitbackportcommits");
+    #[test]
+    fn finds_nothing_without_change_ids() {
+        let mut repo = FakeRepo::new();
+        let root = repo.commit(&[], "root");
+        let a = repo.commit(&[&root], &format!("feature A\n\nChange-Id: {}", CHANGE_ID_A));
+        repo.set_ref("up", &a);
+        let backport = repo.commit(&[&root], "feature A");
 
-    // Output each commit
-    for commit in commits {
-        println!("{}", commit.to_line());
+        assert!(find_all_original_commits(&repo, &commit_info(&repo, &backport), "up").unwrap().is_empty());
     }
 
-    Ok(())
+    #[test]
+    fn detects_applied_commits() {
+        let mut repo = FakeRepo::new();
+        let root = repo.commit(&[], "root");
+        repo.set_ref("base", &root);
+
+        let by_change_id = repo.commit_change(&[&root], &format!("one\n\nChange-Id: {}", CHANGE_ID_A), &["one.c"], "p1");
+        let by_cherry_pick = repo.commit_change(&[&by_change_id], "two", &["two.c"], "p2");
+        let by_patch_id = repo.commit_change(&[&by_cherry_pick], "three", &["three.c"], "p3");
+        let by_title = repo.commit_change(&[&by_patch_id], "four", &["four.c", "4.h"], "p4");
+        let same_title_other_files = repo.commit_change(&[&by_title], "five", &["five.c"], "p5");
+        let missing = repo.commit_change(&[&same_title_other_files], "six", &["six.c"], "p6");
+        repo.set_ref("up", &missing);
+
+        let head = repo.commit_change(&[&root], &format!("one, backported\n\nChange-Id: {}", CHANGE_ID_A), &["one.c"], "q1");
+        let head = repo.commit_change(&[&head], &format!("two\n\n(cherry picked from commit {})", &by_cherry_pick[..12]), &["two.c"], "q2");
+        let head = repo.commit_change(&[&head], "three, reworded", &["three.c"], "p3");
+        let head = repo.commit_change(&[&head], "four", &["4.h", "four.c"], "q4");
+        let head = repo.commit_change(&[&head], "five", &["other.c"], "q5");
+        repo.set_ref("HEAD", &head);
+
+        let applied = |hash: &str| is_commit_already_applied(&repo, &commit_info(&repo, hash), "base").unwrap();
+        assert!(matches!(applied(&root), Some(AppliedBy::Ancestor)));
+        assert!(matches!(applied(&by_change_id), Some(AppliedBy::ChangeId)));
+        assert!(matches!(applied(&by_cherry_pick), Some(AppliedBy::CherryPick)));
+        assert!(matches!(applied(&by_patch_id), Some(AppliedBy::PatchId)));
+        assert!(matches!(applied(&by_title), Some(AppliedBy::TitleAndFiles)));
+        assert!(applied(&same_title_other_files).is_none());
+        assert!(applied(&missing).is_none());
+    }
 }
@@ -0,0 +1,310 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use serde::Serialize;
+use crate::error::{Error, Result};
+use crate::utils::backend::GitBackend;
+use crate::utils::commits::{Action, CommitInfo};
+use crate::utils::metadata::titles_match;
+use crate::utils::output::{self, OutputFormat};
+use crate::utils::repo::Repository;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// File containing the commits to check (one per line)
+    #[arg(long = "commits-file", short = 'F', required = true)]
+    pub commits_file: String,
+
+    /// Branch the commits come from, to check that they are listed oldest first like `git bp sort` lists them
+    #[arg(long = "ref")]
+    pub reference: Option<String>,
+
+    /// Fix what can be fixed automatically and write the result back to the file
+    #[arg(long = "fix")]
+    pub fix: bool,
+}
+
+/// JSON document printed by the lint command
+#[derive(Serialize)]
+struct LintReport {
+    file: String,
+    problems: Vec<Problem>,
+}
+
+/// Something wrong with a line of the commits file
+#[derive(Serialize)]
+struct Problem {
+    /// 1-based line number
+    line: usize,
+    kind: ProblemKind,
+    message: String,
+    /// Whether --fix fixed it
+    fixed: bool,
+}
+
+/// Kinds of problems the lint command finds
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum ProblemKind {
+    /// The line is neither a comment nor a commit
+    Unparseable,
+    /// The hash names no commit
+    UnknownCommit,
+    /// The abbreviated hash names several commits
+    AmbiguousHash,
+    /// The commit is listed on an earlier line already
+    Duplicate,
+    /// The title is not the title of the commit
+    TitleMismatch,
+    /// The Change-Id is not the Change-Id of the commit
+    ChangeIdMismatch,
+    /// The commit is an ancestor of HEAD
+    AlreadyOnHead,
+    /// The commit is listed before a commit it descends from, while the file lists oldest first
+    OutOfOrder,
+}
+
+/// A commit line of the file, with the comment and other non-commit lines before it
+struct Block {
+    /// Lines between the previous commit line and this one
+    before: Vec<String>,
+    /// 1-based line number of the commit line
+    number: usize,
+    text: String,
+    /// Full hash of the commit, when the line names exactly one
+    hash: Option<String>,
+}
+
+/// Handle the lint command - report problems in a commits file, optionally fixing them
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    let repo = Repository::open()?;
+    let content = fs::read_to_string(&args.commits_file)?;
+
+    let mut problems = Vec::new();
+    let mut report = |line: usize, kind: ProblemKind, message: String, fixed: bool| {
+        problems.push(Problem {
+            line,
+            kind,
+            message,
+            fixed,
+        });
+    };
+
+    let mut modelines = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut before = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let object_format = repo.object_format();
+
+    for (i, raw) in content.lines().enumerate() {
+        let number = i + 1;
+        let line = raw.trim();
+        if blocks.is_empty() && before.is_empty() && (line.starts_with("# vim:") || line.starts_with("# vi:")) {
+            modelines.push(raw.to_string());
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            before.push(raw.to_string());
+            continue;
+        }
+
+        let (action, rest) = Action::split_keyword(line);
+        let word = rest.split_whitespace().next().unwrap_or_default();
+        if !object_format.is_hash_like(word) {
+            report(number, ProblemKind::Unparseable, format!("not a commit line: {}", line), false);
+            before.push(raw.to_string());
+            continue;
+        }
+        let mut info = CommitInfo::parse_line(rest)?;
+        // Lines are only rewritten when something on them was fixed
+        let mut rewrite = false;
+
+        let hash = match repo.resolve(&info.hash)? {
+            Some(hash) => Some(hash),
+            None => {
+                let candidates = repo.commits_with_prefix(&info.hash)?;
+                if candidates.is_empty() {
+                    report(number, ProblemKind::UnknownCommit, format!("{} is not a commit", info.hash), false);
+                    None
+                } else {
+                    // The title settles which commit was meant, if it fits only one
+                    let mut matching = Vec::new();
+                    for candidate in &candidates {
+                        let title = repo.title(candidate)?;
+                        if info.title.as_deref().is_some_and(|quoted| title.is_some_and(|title| titles_match(quoted, &title))) {
+                            matching.push(candidate.clone());
+                        }
+                    }
+                    let abbrevs: Vec<&str> = candidates.iter().map(|hash| object_format.abbreviate(hash)).collect();
+                    let message = format!("{} is ambiguous, it could be {}", info.hash, abbrevs.join(", "));
+                    if let ([hash], true) = (matching.as_slice(), args.fix) {
+                        info.hash = hash.clone();
+                        rewrite = true;
+                        report(number, ProblemKind::AmbiguousHash, message, true);
+                        Some(hash.clone())
+                    } else {
+                        report(number, ProblemKind::AmbiguousHash, message, false);
+                        None
+                    }
+                }
+            }
+        };
+
+        let mut text = raw.to_string();
+        if let Some(hash) = &hash {
+            if let Some(&first) = seen.get(hash) {
+                let message = format!("{} is already listed on line {}", object_format.abbreviate(hash), first);
+                report(number, ProblemKind::Duplicate, message, args.fix);
+                if args.fix {
+                    continue;
+                }
+            } else {
+                seen.insert(hash.clone(), number);
+            }
+
+            let metadata = repo.metadata(hash)?.ok_or_else(|| Error::CommitNotFound(hash.clone()))?;
+            if let Some(title) = &info.title {
+                if !metadata.title.as_deref().is_some_and(|actual| titles_match(title, actual)) {
+                    let message = format!("title \"{}\" is not the title of {}: \"{}\"", title,
+                                          object_format.abbreviate(hash), metadata.title.as_deref().unwrap_or_default());
+                    report(number, ProblemKind::TitleMismatch, message, args.fix);
+                    info.title = metadata.title.clone();
+                    rewrite = true;
+                }
+            }
+            if let Some(change_id) = &info.change_id {
+                if metadata.change_id.as_ref() != Some(change_id) {
+                    let message = match &metadata.change_id {
+                        Some(actual) => format!("{} has Change-Id {}, not {}", object_format.abbreviate(hash), actual, change_id),
+                        None => format!("{} has no Change-Id, but {} is given", object_format.abbreviate(hash), change_id),
+                    };
+                    report(number, ProblemKind::ChangeIdMismatch, message, args.fix);
+                    info.change_id = metadata.change_id.clone();
+                    rewrite = true;
+                }
+            }
+            if repo.is_ancestor(hash, "HEAD")? {
+                report(number, ProblemKind::AlreadyOnHead, format!("{} is already on HEAD", object_format.abbreviate(hash)), false);
+            }
+
+            if args.fix && rewrite {
+                text = match action {
+                    Some(action) => format!("{} {}", action.keyword(), info.to_line()),
+                    None => info.to_line(),
+                };
+            }
+        }
+
+        blocks.push(Block {
+            before: std::mem::take(&mut before),
+            number,
+            text,
+            hash,
+        });
+    }
+
+    if let Some(reference) = &args.reference {
+        let ancestors = ancestry(&repo, reference, &blocks)?;
+        for (i, block) in blocks.iter().enumerate() {
+            // Report each commit listed before one of its ancestors once, naming the first such ancestor
+            if let (Some(&j), Some(hash)) = (ancestors[i].iter().find(|&&j| j > i), &block.hash) {
+                let ancestor = &blocks[j];
+                let message = format!("{} is listed before {} on line {}, which it descends from",
+                                      object_format.abbreviate(hash),
+                                      object_format.abbreviate(ancestor.hash.as_deref().unwrap_or_default()),
+                                      ancestor.number);
+                report(block.number, ProblemKind::OutOfOrder, message, args.fix);
+            }
+        }
+        if args.fix {
+            let order = fixed_order(&ancestors);
+            let mut slots: Vec<Option<Block>> = blocks.into_iter().map(Some).collect();
+            blocks = order.iter().filter_map(|&i| slots[i].take()).collect();
+        }
+    }
+
+    problems.sort_by_key(|problem| problem.line);
+    let remaining = problems.iter().filter(|problem| !problem.fixed).count();
+
+    if args.fix && problems.iter().any(|problem| problem.fixed) {
+        let mut lines = modelines;
+        for block in blocks {
+            lines.extend(block.before);
+            lines.push(block.text);
+        }
+        lines.extend(before);
+        fs::write(&args.commits_file, lines.join("\n") + "\n")?;
+    }
+
+    match format {
+        OutputFormat::Text => {
+            for problem in &problems {
+                let fixed = if problem.fixed { " (fixed)" } else { "" };
+                println!("{}:{}: {}{}", args.commits_file, problem.line, problem.message, fixed);
+            }
+        }
+        OutputFormat::Json => output::print_json(&LintReport {
+            file: args.commits_file.clone(),
+            problems,
+        })?,
+    }
+
+    if remaining > 0 {
+        return Err(Error::LintProblems {
+            count: remaining,
+            file: args.commits_file,
+        });
+    }
+    Ok(())
+}
+
+/// For every block, find the blocks listing commits it descends from on the reference branch
+///
+/// Commits that are not on the reference branch have no ancestors here.
+fn ancestry(repo: &Repository, reference: &str, blocks: &[Block]) -> Result<Vec<Vec<usize>>> {
+    let history = repo.history(reference)?;
+    let positions: Vec<Option<usize>> = blocks.iter()
+        .map(|block| block.hash.as_deref().and_then(|hash| history.position(hash)))
+        .collect();
+
+    Ok(positions.iter()
+        .map(|&descendant| {
+            (0..blocks.len())
+                .filter(|&j| match (positions[j], descendant) {
+                    (Some(ancestor), Some(descendant)) => ancestor != descendant && history.is_ancestor_at(ancestor, descendant),
+                    _ => false,
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// Order the blocks so that every block comes after its ancestors
+///
+/// Each block pulls the ancestors listed after it in front of itself, in file
+/// order, so blocks only move when they have to and a file without problems
+/// keeps its order.
+fn fixed_order(ancestors: &[Vec<usize>]) -> Vec<usize> {
+    fn place(i: usize, ancestors: &[Vec<usize>], placed: &mut [bool], order: &mut Vec<usize>) {
+        if placed[i] {
+            return;
+        }
+        placed[i] = true;
+        for &j in &ancestors[i] {
+            place(j, ancestors, placed, order);
+        }
+        order.push(i);
+    }
+
+    let mut placed = vec![false; ancestors.len()];
+    let mut order = Vec::with_capacity(ancestors.len());
+    for i in 0..ancestors.len() {
+        place(i, ancestors, &mut placed, &mut order);
+    }
+    order
+}
@@ -0,0 +1,81 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use log::debug;
+use serde::Serialize;
+use crate::error::Result;
+use crate::commands::fix;
+use crate::utils::commits::{CommitEntry, CommitInfo};
+use crate::utils::output::{self, OutputFormat};
+use crate::utils::repo::Repository;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Base commit to start checking from (exclusive)
+    #[arg(long = "base", required = true)]
+    pub base: String,
+
+    /// Reference branch to search for missing commits
+    #[arg(long = "ref", required = true)]
+    pub ref_branch: String,
+
+    /// Only consider commits touching these paths (default: files changed in base..HEAD)
+    pub pathspec: Vec<String>,
+}
+
+/// JSON document printed by the missing command
+#[derive(Serialize)]
+struct MissingReport {
+    /// Paths the commits were limited to
+    paths: Vec<String>,
+    commits: Vec<CommitInfo>,
+}
+
+/// Handle the missing command - list upstream commits touching our paths that were never backported
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    let repo = Repository::open()?;
+
+    let paths = if args.pathspec.is_empty() {
+        repo.files_changed_in_range(&args.base, "HEAD")?
+    } else {
+        args.pathspec
+    };
+    debug!("Looking for commits in {}..{} touching {} path(s)", args.base, args.ref_branch, paths.len());
+
+    let candidates = if paths.is_empty() {
+        Vec::new()
+    } else {
+        repo.commits_touching(&args.base, &args.ref_branch, &paths)?
+    };
+    debug!("Found {} candidate commit(s)", candidates.len());
+
+    // Compute all patch-ids at once instead of once per candidate
+    repo.load_patch_ids(&candidates)?;
+
+    let mut commits = Vec::new();
+    for hash in candidates {
+        let mut commit = CommitInfo::from_hash(hash);
+        commit.fetch_change_id_if_missing(&repo)?;
+        commit.fetch_title_if_missing(&repo)?;
+
+        match fix::is_commit_already_applied(&repo, &commit, &args.base)? {
+            Some(applied_by) => debug!("Commit {} already applied ({:?})", commit.hash, applied_by),
+            None => commits.push(commit),
+        }
+    }
+
+    match format {
+        OutputFormat::Text => {
+            let entries: Vec<CommitEntry> = commits.into_iter()
+                .map(|commit| CommitEntry::with_comments(commit, Vec::new()))
+                .collect();
+            fix::output_commits_file(&entries)?;
+        }
+        OutputFormat::Json => output::print_json(&MissingReport { paths, commits })?,
+    }
+
+    Ok(())
+}
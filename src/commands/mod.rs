@@ -8,3 +8,9 @@ pub mod sort;
 pub mod pick;
 pub mod vim;
 pub mod fix;
+pub mod cache;
+pub mod status;
+pub mod missing;
+pub mod deps;
+pub mod diff;
+pub mod lint;
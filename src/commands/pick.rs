@@ -4,28 +4,894 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 
-use crate::utils::commits::CommitsParser;
+use std::fs;
+use std::path::PathBuf;
+use serde::Serialize;
+use crate::error::{Error, Result};
+use crate::utils::backend::GitBackend;
+use crate::utils::commits::{Action, CommitEntry, CommitInfo, CommitsParser};
+use crate::utils::output::{self, OutputFormat};
+use crate::utils::repo::Repository;
 
 #[derive(clap::Args)]
 pub struct Args {
     /// Commit hashes to generate cherry-pick commands for
-    #[arg(required_unless_present = "commits_file")]
+    #[arg(required_unless_present_any = ["commits_file", "continue_pick", "skip", "abort", "amend_picked"])]
     pub commits: Vec<String>,
 
     /// File containing commit hashes to cherry-pick (one per line)
     #[arg(long = "commits-file", short = 'F', conflicts_with = "commits")]
     pub commits_file: Option<String>,
+
+    /// Run the cherry-picks instead of printing them, stopping on conflicts
+    #[arg(long = "apply", group = "sequencer")]
+    pub apply: bool,
+
+    /// Resume an interrupted --apply run after the conflict has been resolved
+    #[arg(long = "continue", group = "sequencer", conflicts_with_all = ["commits", "commits_file"])]
+    pub continue_pick: bool,
+
+    /// Skip the commit that stopped an --apply run and resume with the next one
+    #[arg(long = "skip", group = "sequencer", conflicts_with_all = ["commits", "commits_file"])]
+    pub skip: bool,
+
+    /// Abort an --apply run and reset HEAD to where it was before the run started
+    #[arg(long = "abort", group = "sequencer", conflicts_with_all = ["commits", "commits_file"])]
+    pub abort: bool,
+
+    /// Print a `git rebase -i` todo list instead of cherry-pick commands
+    #[arg(long = "todo", conflicts_with = "sequencer")]
+    pub todo: bool,
+
+    /// Pick the commits with an interactive rebase running the todo list printed by --todo
+    #[arg(long = "rebase", group = "sequencer")]
+    pub rebase: bool,
+
+    /// Add a trailer to every picked commit, e.g. "Upstream-Commit: %H"
+    ///
+    /// %H and %h expand to the full and abbreviated hash of the original
+    /// commit, and a trailer without a value, e.g. "Backported-by", gets the
+    /// committer's name and email. Trailers listed in the multi-valued
+    /// `bp.pick.trailer` git config are added as well.
+    #[arg(long = "trailer", value_name = "TRAILER")]
+    pub trailers: Vec<String>,
+
+    /// Give every picked commit a new Change-Id, keeping the upstream one in a Was-Change-Id trailer
+    ///
+    /// Gerrit rejects a Change-Id that is already used by another change, so
+    /// backports need their own. Can also be enabled with `bp.pick.rewriteChangeId`.
+    #[arg(long = "rewrite-change-id")]
+    pub rewrite_change_id: bool,
+
+    /// Amend HEAD after it was picked from the given commit, run by generated commands
+    #[arg(long = "amend-picked", value_name = "COMMIT", hide = true,
+          conflicts_with_all = ["commits", "commits_file", "sequencer", "todo"])]
+    pub amend_picked: Option<String>,
+
+    /// Add the "(cherry picked from commit ...)" line when amending with --amend-picked
+    #[arg(long = "record-origin", hide = true, requires = "amend_picked")]
+    pub record_origin: bool,
+
+    /// Add a Signed-off-by trailer when amending with --amend-picked
+    #[arg(long = "signoff", hide = true, requires = "amend_picked")]
+    pub signoff: bool,
+
+    /// The commit was folded into HEAD by a squash or fixup, when amending with --amend-picked
+    #[arg(long = "fold", value_name = "ACTION", hide = true, requires = "amend_picked")]
+    pub fold: Option<Action>,
+}
+
+/// JSON document printed by the pick command
+#[derive(Serialize)]
+struct PickOutput {
+    commits: Vec<PickedCommit>,
+}
+
+/// A commit to pick together with the command that picks it
+#[derive(Serialize)]
+struct PickedCommit {
+    #[serde(flatten)]
+    commit: CommitInfo,
+    action: Action,
+    /// Shell command carrying out the action, none for dropped commits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+}
+
+/// How commits are picked, set per repository in the `bp.pick.*` git config
+struct PickOptions {
+    /// Add a Signed-off-by trailer (`bp.pick.signoff`, on by default)
+    signoff: bool,
+    /// Add a "(cherry picked from commit ...)" line (`bp.pick.recordOrigin`, on by default)
+    record_origin: bool,
+    /// Merge strategy to pick with (`bp.pick.strategy`)
+    strategy: Option<String>,
+    /// Parent number merge commits are picked relative to (`bp.pick.mainline`)
+    mainline: Option<String>,
+    /// Trailers added to every picked commit (`bp.pick.trailer` and --trailer), placeholders unexpanded
+    trailers: Vec<String>,
+    /// Replace the Change-Id of picked commits (`bp.pick.rewriteChangeId` and --rewrite-change-id)
+    rewrite_change_id: bool,
+}
+
+impl PickOptions {
+    /// Read the options from git config, adding the trailers given on the command line
+    fn load(repo: &Repository, trailers: Vec<String>, rewrite_change_id: bool) -> Result<Self> {
+        let mainline = repo.config_string("bp.pick.mainline")?;
+        if let Some(mainline) = &mainline {
+            if mainline.parse::<u32>().map_or(true, |parent| parent == 0) {
+                return Err(Error::InvalidConfig {
+                    key: "bp.pick.mainline",
+                    value: mainline.clone(),
+                    expected: "a parent number",
+                });
+            }
+        }
+
+        Ok(Self {
+            signoff: repo.config_bool("bp.pick.signoff")?.unwrap_or(true),
+            record_origin: repo.config_bool("bp.pick.recordOrigin")?.unwrap_or(true),
+            strategy: repo.config_string("bp.pick.strategy")?,
+            mainline,
+            trailers: repo.config_strings("bp.pick.trailer")?.into_iter().chain(trailers).collect(),
+            rewrite_change_id: rewrite_change_id || repo.config_bool("bp.pick.rewriteChangeId")?.unwrap_or(false),
+        })
+    }
+
+    /// Arguments passed to git to cherry-pick a single commit
+    ///
+    /// Commits folded into HEAD are applied without committing, the amend
+    /// that folds them records their origin.
+    fn cherry_pick_args(&self, hash: &str, is_merge: bool, fold: bool) -> Vec<String> {
+        let mut args = vec!["cherry-pick".to_string()];
+        if fold {
+            args.push("--no-commit".to_string());
+        } else {
+            if self.record_origin {
+                args.push("-x".to_string());
+            }
+            if self.signoff {
+                args.push("--signoff".to_string());
+            }
+        }
+        if let Some(strategy) = &self.strategy {
+            args.push(format!("--strategy={}", strategy));
+        }
+        if let (true, Some(mainline)) = (is_merge, &self.mainline) {
+            args.extend(["-m".to_string(), mainline.clone()]);
+        }
+        args.push(hash.to_string());
+        args
+    }
+
+    /// Trailers for a commit picked from `hash`, with placeholders expanded
+    ///
+    /// %H and %h become the full and abbreviated hash, and a trailer that is
+    /// only a token gets the committer's identity as its value.
+    fn trailers_for(&self, repo: &Repository, hash: &str) -> Result<Vec<String>> {
+        let mut identity = None;
+        let mut trailers = Vec::new();
+        for template in &self.trailers {
+            let trailer = template.replace("%H", hash).replace("%h", repo.object_format().abbreviate(hash));
+            if trailer.contains(':') {
+                trailers.push(trailer);
+                continue;
+            }
+
+            if identity.is_none() {
+                identity = Some(committer_identity(repo)?);
+            }
+            trailers.push(format!("{}: {}", trailer.trim(), identity.as_deref().unwrap_or_default()));
+        }
+        Ok(trailers)
+    }
+
+    /// Changes still to be made to HEAD after `action` was carried out for the commit `hash`
+    ///
+    /// When it was picked by git cherry-pick, the origin line and sign-off
+    /// were added by git already. A commit folded into HEAD keeps the
+    /// Change-Id and sign-off of HEAD.
+    fn amendment(&self, repo: &Repository, hash: &str, action: Action, by_cherry_pick: bool) -> Result<Amendment> {
+        let fold = action.folds().then_some(action);
+        Ok(Amendment {
+            record_origin: self.record_origin && (fold.is_some() || !by_cherry_pick),
+            signoff: self.signoff && fold.is_none() && !by_cherry_pick,
+            rewrite_change_id: self.rewrite_change_id && fold.is_none(),
+            fold,
+            trailers: self.trailers_for(repo, hash)?,
+        })
+    }
+}
+
+/// Changes made to the message of a commit after it was picked
+struct Amendment {
+    /// Add the "(cherry picked from commit ...)" line
+    record_origin: bool,
+    /// Add a Signed-off-by trailer
+    signoff: bool,
+    /// Replace the Change-Id with a new one, keeping the old one as Was-Change-Id
+    rewrite_change_id: bool,
+    /// The commit was folded into HEAD by this squash or fixup
+    fold: Option<Action>,
+    /// Further trailers, placeholders already expanded
+    trailers: Vec<String>,
+}
+
+impl Amendment {
+    /// Check whether the message is left as it is
+    fn is_empty(&self) -> bool {
+        !self.record_origin && !self.signoff && !self.rewrite_change_id && self.fold.is_none() && self.trailers.is_empty()
+    }
+
+    /// Command making this amendment to a commit picked from `hash`, for generated commands
+    fn command(&self, hash: &str) -> String {
+        let mut command = format!("git bp pick --amend-picked {}", hash);
+        if self.record_origin {
+            command.push_str(" --record-origin");
+        }
+        if self.signoff {
+            command.push_str(" --signoff");
+        }
+        if self.rewrite_change_id {
+            command.push_str(" --rewrite-change-id");
+        }
+        if let Some(fold) = self.fold {
+            command.push_str(&format!(" --fold {}", fold.keyword()));
+        }
+        for trailer in &self.trailers {
+            command.push_str(&format!(" --trailer {}", shell_quote(trailer)));
+        }
+        command
+    }
+
+    /// Amend HEAD, which was just picked from `hash` or had it folded in
+    ///
+    /// The "(cherry picked from commit ...)" line and the sign-off are added
+    /// like `git cherry-pick -x --signoff` adds them, followed by the
+    /// Change-Ids and the other trailers. Changes staged by a fold are
+    /// committed along with the message.
+    fn apply(&self, repo: &Repository, hash: &str) -> Result<()> {
+        let mut message = repo.git_output(&["show", "-s", "--format=%B", "HEAD"])?;
+
+        let mut folded_change_ids = Vec::new();
+        if let Some(fold) = self.fold {
+            // Change-Ids must stay in the last paragraph, so the folded one becomes a Was-Change-Id
+            let (folded, change_ids) = without_change_ids(&repo.git_output(&["show", "-s", "--format=%B", hash])?);
+            folded_change_ids = change_ids;
+            if fold == Action::Squash {
+                message = with_squashed_message(&message, &folded);
+            }
+        }
+
+        if self.record_origin {
+            message = with_cherry_picked_line(&message, hash);
+        }
+
+        let mut trailers = Vec::new();
+        if self.signoff {
+            trailers.push(format!("Signed-off-by: {}", committer_identity(repo)?));
+        }
+        if self.rewrite_change_id {
+            let (rest, change_ids) = without_change_ids(&message);
+            trailers.extend(change_ids.iter().map(|change_id| format!("Was-Change-Id: {}", change_id)));
+            trailers.push(format!("Change-Id: {}", new_change_id(repo, &rest)?));
+            message = rest;
+        }
+        trailers.extend(folded_change_ids.iter().map(|change_id| format!("Was-Change-Id: {}", change_id)));
+        trailers.extend(self.trailers.iter().cloned());
+
+        if !trailers.is_empty() {
+            // Like --signoff, a trailer is not repeated when it is already the last one
+            let mut args = vec!["interpret-trailers", "--if-exists", "addIfDifferentNeighbor"];
+            for trailer in &trailers {
+                args.extend(["--trailer", trailer.as_str()]);
+            }
+            message = repo.git_with_input(&args, &message)?;
+        }
+
+        let args = ["commit", "--amend", "--quiet", "--allow-empty", "--no-verify", "--cleanup=verbatim", "-F", "-"];
+        repo.git_with_input(&args, &format!("{}\n", message.trim_end()))?;
+        Ok(())
+    }
 }
 
 /// Handle the pick command - generate git cherry-pick commands
-pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    let repo = Repository::open()?;
+
+    if let Some(hash) = args.amend_picked {
+        if let Some(action) = args.fold.filter(|action| !action.folds()) {
+            return Err(Error::InvalidFold(action.keyword()));
+        }
+
+        // Generated commands spell out every change, so git config is not read again
+        let amendment = Amendment {
+            record_origin: args.record_origin,
+            signoff: args.signoff,
+            rewrite_change_id: args.rewrite_change_id,
+            fold: args.fold,
+            trailers: args.trailers,
+        };
+        return amendment.apply(&repo, &repo.resolve_commit(&hash)?);
+    }
+
+    let sequencer = args.apply || args.continue_pick || args.skip || args.abort || args.rebase;
+    if (sequencer || args.todo) && format != OutputFormat::Text {
+        return Err(Error::JsonNotSupported("by pick except when generating cherry-pick commands"));
+    }
+
+    if args.continue_pick {
+        return continue_apply(&repo, false);
+    }
+    if args.skip {
+        return continue_apply(&repo, true);
+    }
+    if args.abort {
+        return abort_apply(&repo);
+    }
+
+    let options = PickOptions::load(&repo, args.trailers, args.rewrite_change_id)?;
+
+    if args.todo || args.rebase {
+        let (entries, _) = CommitsParser::get_entries(args.commits, args.commits_file)?;
+        let todo = todo_list(&repo, &options, entries)?;
+        if args.rebase {
+            return start_rebase(&repo, &options, &todo);
+        }
+        print!("{}", todo);
+        return Ok(());
+    }
+
     // 获取commit列表：要么从命令行参数，要么从文件
-    let (commit_infos, _) = CommitsParser::get_commits(args.commits, args.commits_file)?;
+    let (entries, _) = CommitsParser::get_entries(args.commits, args.commits_file)?;
+
+    if args.apply {
+        let items = entries.iter()
+            .map(|entry| PickItem {
+                action: entry.action(),
+                hash: entry.commit.hash.clone(),
+            })
+            .collect();
+        return start_apply(&repo, options, items);
+    }
 
     // 生成cherry-pick命令
-    for commit_info in commit_infos {
-        println!("git cherry-pick -x --signoff {}", commit_info.hash);
+    match format {
+        OutputFormat::Text => {
+            // Nothing may run past an edit, however the commands are run, so the rest is commented out
+            let mut prefix = "";
+            for entry in entries {
+                match pick_command(&repo, &options, entry.action(), &entry.commit.hash)? {
+                    Some(command) => println!("{}{}", prefix, command),
+                    None => println!("# drop {}", entry.commit.hash),
+                }
+                if entry.action() == Action::Edit && prefix.is_empty() {
+                    println!("# Run the commands below once the commit is amended");
+                    prefix = "# ";
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let mut commits = Vec::new();
+            for entry in entries {
+                let action = entry.action();
+                let mut commit_info = entry.commit;
+                commit_info.fetch_change_id_if_missing(&repo)?;
+                commit_info.fetch_title_if_missing(&repo)?;
+                commits.push(PickedCommit {
+                    command: pick_command(&repo, &options, action, &commit_info.hash)?,
+                    commit: commit_info,
+                    action,
+                });
+            }
+            output::print_json(&PickOutput { commits })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Shell command carrying out the action for a single commit, amending its message afterwards if needed
+///
+/// Dropped commits need no command. The command of an edited commit says
+/// where it stopped, the commands after it are left to be run once the commit
+/// is amended.
+fn pick_command(repo: &Repository, options: &PickOptions, action: Action, hash: &str) -> Result<Option<String>> {
+    if action == Action::Drop {
+        return Ok(None);
+    }
+
+    // Unknown commits are left for git cherry-pick to complain about
+    let full_hash = repo.resolve(hash)?;
+    let is_merge = match &full_hash {
+        Some(full_hash) => is_merge(repo, full_hash)?,
+        None => false,
+    };
+
+    let args: Vec<String> = options.cherry_pick_args(hash, is_merge, action.folds()).iter().map(|arg| shell_quote(arg)).collect();
+    let mut command = format!("git {}", args.join(" "));
+
+    let full_hash = full_hash.as_deref().unwrap_or(hash);
+    let amendment = options.amendment(repo, full_hash, action, true)?;
+    if !amendment.is_empty() {
+        command.push_str(&format!(" && {}", amendment.command(full_hash)));
+    }
+    if action == Action::Edit {
+        let stop = format!("Stopped at {}; amend it, then run the remaining commands", repo.object_format().abbreviate(full_hash));
+        command.push_str(&format!(" && echo {}", shell_quote(&stop)));
+    }
+    Ok(Some(command))
+}
+
+/// Check whether a commit has more than one parent
+fn is_merge(repo: &Repository, hash: &str) -> Result<bool> {
+    Ok(repo.metadata(hash)?.is_some_and(|metadata| metadata.parents.len() > 1))
+}
+
+/// Name and email of the committer, as used in Signed-off-by trailers
+fn committer_identity(repo: &Repository) -> Result<String> {
+    // The identity is followed by a timestamp and time zone
+    let ident = repo.git_output(&["var", "GIT_COMMITTER_IDENT"])?;
+    let ident = ident.trim();
+    Ok(match ident.rfind('>') {
+        Some(end) => ident[..=end].to_string(),
+        None => ident.to_string(),
+    })
+}
+
+/// Generate a Change-Id for HEAD with the given message, the way Gerrit's commit-msg hook does
+///
+/// The id is the hash of the tree, parent, identities and message, so it is
+/// unique to this backport rather than shared with the upstream commit.
+fn new_change_id(repo: &Repository, message: &str) -> Result<String> {
+    let mut input = format!("tree {}\n", repo.git_output(&["rev-parse", "--verify", "HEAD^{tree}"])?.trim());
+    if let Some(parent) = repo.resolve("HEAD^")? {
+        input.push_str(&format!("parent {}\n", parent));
+    }
+    input.push_str(&format!("author {}\n", repo.git_output(&["var", "GIT_AUTHOR_IDENT"])?.trim()));
+    input.push_str(&format!("committer {}\n", repo.git_output(&["var", "GIT_COMMITTER_IDENT"])?.trim()));
+    input.push('\n');
+    input.push_str(message);
+
+    let hash = repo.git_with_input(&["hash-object", "--stdin"], &input)?;
+    // Change-Ids are always 40 digits, also in SHA-256 repositories
+    let hash = hash.trim().get(..40).ok_or_else(|| Error::InvalidGitOutput {
+        args: vec!["hash-object".to_string(), "--stdin".to_string()],
+        output: hash.clone(),
+    })?;
+    Ok(format!("I{}", hash))
+}
+
+/// Remove the Change-Id trailers from the last paragraph of a message, returning them
+fn without_change_ids(message: &str) -> (String, Vec<String>) {
+    let message = message.trim_end();
+    let (body, last_paragraph) = match message.rsplit_once("\n\n") {
+        Some((body, paragraph)) => (Some(body), paragraph),
+        None => (None, message),
+    };
+
+    let mut change_ids = Vec::new();
+    let mut kept = Vec::new();
+    for line in last_paragraph.lines() {
+        match line.strip_prefix("Change-Id:") {
+            Some(change_id) if body.is_some() => change_ids.push(change_id.trim().to_string()),
+            _ => kept.push(line),
+        }
+    }
+
+    let rest = match body {
+        Some(body) if kept.is_empty() => format!("{}\n", body),
+        Some(body) => format!("{}\n\n{}\n", body, kept.join("\n")),
+        None => format!("{}\n", message),
+    };
+    (rest, change_ids)
+}
+
+/// Quote a word for the shell, leaving words that need no quoting alone
+fn shell_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+^~".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        return word.to_string();
     }
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+/// Build a `git rebase -i` todo list picking the commits
+///
+/// Each pick is followed by an exec line amending the picked commit the way
+/// `git cherry-pick` with the configured options would have, and the
+/// comments of the commits file are kept as todo comments. Squashes become
+/// fixups whose exec line inserts the folded message, so the trailers stay in
+/// the last paragraph. `git rebase` cannot pick a merge relative to one of
+/// its parents, so with `bp.pick.mainline` set merges are cherry-picked by
+/// exec lines instead.
+fn todo_list(repo: &Repository, options: &PickOptions, entries: Vec<CommitEntry>) -> Result<String> {
+    check_first_fold(entries.iter().map(|entry| (entry.action(), entry.commit.hash.as_str())))?;
+
+    let mut todo = String::new();
+    for mut entry in entries {
+        // git would only notice a bad hash once the rebase reaches it
+        entry.commit.hash = repo.resolve_commit(&entry.commit.hash)?;
+        entry.commit.fetch_title_if_missing(repo)?;
+
+        for comment in &entry.comments {
+            let trimmed = comment.trim();
+            // Lines the commits file treated as comments must not be read as todo commands
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                todo.push_str(&format!("{}\n", comment));
+            } else {
+                todo.push_str(&format!("# {}\n", comment));
+            }
+        }
+
+        let hash = &entry.commit.hash;
+        let action = entry.action();
+        if action != Action::Drop && options.mainline.is_some() && is_merge(repo, hash)? {
+            if let Some(title) = &entry.commit.title {
+                todo.push_str(&format!("# {}\n", title));
+            }
+            let args: Vec<String> = options.cherry_pick_args(hash, true, action.folds())
+                .iter()
+                .map(|arg| shell_quote(arg))
+                .collect();
+            todo.push_str(&format!("exec git {}\n", args.join(" ")));
+            let amendment = options.amendment(repo, hash, action, true)?;
+            if !amendment.is_empty() {
+                todo.push_str(&format!("exec {}\n", amendment.command(hash)));
+            }
+            if action == Action::Edit {
+                todo.push_str("break\n");
+            }
+            continue;
+        }
+
+        let keyword = if action == Action::Squash { Action::Fixup.keyword() } else { action.keyword() };
+        match &entry.commit.title {
+            Some(title) => todo.push_str(&format!("{} {} {}\n", keyword, hash, title)),
+            None => todo.push_str(&format!("{} {}\n", keyword, hash)),
+        }
+        if action == Action::Drop {
+            continue;
+        }
+        let amendment = options.amendment(repo, hash, action, false)?;
+        if !amendment.is_empty() {
+            todo.push_str(&format!("exec {}\n", amendment.command(hash)));
+        }
+    }
+    Ok(todo)
+}
 
+/// Fail on a squash or fixup that has no commit before it to be folded into
+///
+/// Dropped commits don't count, like in a `git rebase -i` todo list.
+fn check_first_fold<'a>(items: impl IntoIterator<Item = (Action, &'a str)>) -> Result<()> {
+    match items.into_iter().find(|(action, _)| *action != Action::Drop) {
+        Some((action, hash)) if action.folds() => Err(Error::NothingToFold {
+            action: action.keyword(),
+            hash: hash.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Run the todo list with `git rebase -i`, leaving conflicts to git's own --continue/--skip/--abort
+fn start_rebase(repo: &Repository, options: &PickOptions, todo: &str) -> Result<()> {
+    if PickState::load(repo)?.is_some() {
+        return Err(Error::PickInProgress);
+    }
+
+    let todo_path = repo.git_path("bp-pick-todo")?;
+    fs::write(&todo_path, todo)?;
+
+    // git runs the sequence editor with the path of its todo list, which is replaced by ours
+    let editor = format!("cp {}", shell_quote(&todo_path.display().to_string()));
+    let strategy = options.strategy.as_ref().map(|strategy| format!("--strategy={}", strategy));
+    let mut args = vec!["rebase", "-i"];
+    args.extend(strategy.as_deref());
+    args.push("HEAD");
+    let succeeded = repo.git_status_with_env(&args, &[("GIT_SEQUENCE_EDITOR", &editor)]);
+    fs::remove_file(&todo_path)?;
+
+    if !succeeded? {
+        return Err(Error::RebaseStopped);
+    }
     Ok(())
 }
+
+/// Insert the message of a squashed commit before the trailer block of a message
+fn with_squashed_message(message: &str, squashed: &str) -> String {
+    let message = message.trim_end();
+    let squashed = squashed.trim_end();
+    match message.rsplit_once("\n\n") {
+        Some((body, trailers)) if trailers.lines().all(is_trailer_line) => {
+            format!("{}\n\n{}\n\n{}\n", body, squashed, trailers)
+        }
+        _ => format!("{}\n\n{}\n", message, squashed),
+    }
+}
+
+/// Append a "(cherry picked from commit <hash>)" line the way `git cherry-pick -x` does
+///
+/// The line joins the trailer block at the end of the message, or starts a new one.
+fn with_cherry_picked_line(message: &str, hash: &str) -> String {
+    let message = message.trim_end();
+    let ends_with_trailers = message.rsplit_once("\n\n")
+        .is_some_and(|(_, paragraph)| paragraph.lines().all(is_trailer_line));
+    let separator = if ends_with_trailers { "\n" } else { "\n\n" };
+    format!("{}{}(cherry picked from commit {})\n", message, separator, hash)
+}
+
+/// Check whether a line belongs in a trailer block, e.g. `Signed-off-by: ...`
+fn is_trailer_line(line: &str) -> bool {
+    if line.starts_with("(cherry picked from commit ") {
+        return true;
+    }
+    line.split_once(": ").is_some_and(|(token, _)| {
+        !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+/// A commit of a `pick --apply` run and what to do with it
+#[derive(Clone)]
+struct PickItem {
+    action: Action,
+    hash: String,
+}
+
+impl PickItem {
+    /// Parse a line of the state files, where the action may be missing
+    fn parse(line: &str) -> Self {
+        let (action, hash) = Action::split_keyword(line);
+        Self {
+            action: action.unwrap_or(Action::Pick),
+            hash: hash.to_string(),
+        }
+    }
+
+    /// Format the item as a line of the state files
+    fn to_line(&self) -> String {
+        format!("{} {}", self.action.keyword(), self.hash)
+    }
+}
+
+/// Progress of a `pick --apply` run, kept in `.git/bp-pick/` so it survives conflicts
+///
+/// The layout mirrors `.git/rebase-merge/`: `orig-head` holds the commit HEAD
+/// pointed to before the run, `done` lists the commits already handled and
+/// `todo` lists the remaining ones, the first of which is the current commit,
+/// each preceded by its action. `pre-pick-head` holds the commit HEAD pointed
+/// to before the current commit was picked, and `picked-head` the commit the
+/// run stopped at to edit, which is already amended.
+/// `trailers` holds the trailers added to every picked commit and
+/// `rewrite-change-id` exists when Change-Ids are replaced, so --continue
+/// keeps the options given when the run started.
+struct PickState {
+    dir: PathBuf,
+    orig_head: String,
+    pre_pick_head: Option<String>,
+    picked_head: Option<String>,
+    done: Vec<PickItem>,
+    todo: Vec<PickItem>,
+    trailers: Vec<String>,
+    rewrite_change_id: bool,
+}
+
+impl PickState {
+    /// Load the state of an interrupted run, if there is one
+    fn load(repo: &Repository) -> Result<Option<Self>> {
+        let dir = repo.git_path("bp-pick")?;
+        if !dir.exists() {
+            return Ok(None);
+        }
+
+        let read_list = |name: &str| -> Result<Vec<String>> {
+            let path = dir.join(name);
+            if !path.exists() {
+                return Ok(Vec::new());
+            }
+            let content = fs::read_to_string(path)?;
+            Ok(content.lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect())
+        };
+
+        let orig_head = fs::read_to_string(dir.join("orig-head"))?.trim().to_string();
+        let pre_pick_head = read_list("pre-pick-head")?.pop();
+        let picked_head = read_list("picked-head")?.pop();
+        let done = read_list("done")?.iter().map(|line| PickItem::parse(line)).collect();
+        let todo = read_list("todo")?.iter().map(|line| PickItem::parse(line)).collect();
+        let trailers = read_list("trailers")?;
+        let rewrite_change_id = dir.join("rewrite-change-id").exists();
+
+        Ok(Some(Self {
+            dir,
+            orig_head,
+            pre_pick_head,
+            picked_head,
+            done,
+            todo,
+            trailers,
+            rewrite_change_id,
+        }))
+    }
+
+    /// Write the state to disk
+    fn save(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let join = |lines: &mut dyn Iterator<Item = String>| -> String {
+            lines.map(|line| format!("{}\n", line)).collect()
+        };
+
+        fs::write(self.dir.join("orig-head"), format!("{}\n", self.orig_head))?;
+        fs::write(self.dir.join("pre-pick-head"), join(&mut self.pre_pick_head.iter().cloned()))?;
+        fs::write(self.dir.join("picked-head"), join(&mut self.picked_head.iter().cloned()))?;
+        fs::write(self.dir.join("done"), join(&mut self.done.iter().map(PickItem::to_line)))?;
+        fs::write(self.dir.join("todo"), join(&mut self.todo.iter().map(PickItem::to_line)))?;
+        fs::write(self.dir.join("trailers"), join(&mut self.trailers.iter().cloned()))?;
+        if self.rewrite_change_id {
+            fs::write(self.dir.join("rewrite-change-id"), "")?;
+        }
+        Ok(())
+    }
+
+    /// Remove the state directory once the run is finished or aborted
+    fn remove(&self) -> Result<()> {
+        fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+
+    /// Mark the current commit as handled
+    fn advance(&mut self) -> Result<()> {
+        if !self.todo.is_empty() {
+            let item = self.todo.remove(0);
+            self.done.push(item);
+        }
+        self.pre_pick_head = None;
+        self.picked_head = None;
+        self.save()
+    }
+}
+
+/// Start a new `pick --apply` run
+fn start_apply(repo: &Repository, options: PickOptions, items: Vec<PickItem>) -> Result<()> {
+    if PickState::load(repo)?.is_some() {
+        return Err(Error::PickInProgress);
+    }
+    check_first_fold(items.iter().map(|item| (item.action, item.hash.as_str())))?;
+
+    let mut state = PickState {
+        dir: repo.git_path("bp-pick")?,
+        orig_head: repo.resolve_commit("HEAD")?,
+        pre_pick_head: None,
+        picked_head: None,
+        done: Vec::new(),
+        todo: items,
+        trailers: options.trailers.clone(),
+        rewrite_change_id: options.rewrite_change_id,
+    };
+    state.save()?;
+
+    run_apply(repo, &options, &mut state)
+}
+
+/// Resume an interrupted run, either finishing or skipping the current commit
+///
+/// The current commit is the one that could not be applied, or the one the
+/// run stopped at to edit.
+fn continue_apply(repo: &Repository, skip: bool) -> Result<()> {
+    let mut state = PickState::load(repo)?
+        .ok_or(Error::NoPickInProgress)?;
+    let mut options = PickOptions::load(repo, Vec::new(), state.rewrite_change_id)?;
+    options.trailers = state.trailers.clone();
+
+    let current = state.todo.first().cloned();
+    if let Some(item) = current.as_ref().filter(|item| item.action.folds()) {
+        // Folds apply the commit without committing, so git has no cherry-pick in progress
+        if skip {
+            let args = ["reset", "--merge"];
+            if !repo.git_status(&args)? {
+                return Err(Error::git_exited(&args));
+            }
+        } else {
+            finish_pick(repo, &options, item.action, &repo.resolve_commit(&item.hash)?)?;
+        }
+    } else if cherry_pick_in_progress(repo)? {
+        let action = if skip { "--skip" } else { "--continue" };
+        let args = ["cherry-pick", action];
+        if !repo.git_status(&args)? {
+            return Err(Error::git_exited(&args));
+        }
+
+        if let (false, Some(item)) = (skip, &current) {
+            finish_pick(repo, &options, item.action, &repo.resolve_commit(&item.hash)?)?;
+        }
+    } else if let (false, Some(item)) = (skip, &current) {
+        // The conflict may have been resolved with `git cherry-pick --continue`, which committed the pick unamended
+        let head = repo.resolve_commit("HEAD")?;
+        let committed = state.pre_pick_head.as_ref().is_some_and(|pre_pick_head| *pre_pick_head != head);
+        if committed && state.picked_head.is_none() {
+            finish_pick(repo, &options, item.action, &repo.resolve_commit(&item.hash)?)?;
+        }
+    }
+
+    state.advance()?;
+    run_apply(repo, &options, &mut state)
+}
+
+/// Abort the run and reset HEAD to where it was before the run started
+fn abort_apply(repo: &Repository) -> Result<()> {
+    let state = PickState::load(repo)?
+        .ok_or(Error::NoPickInProgress)?;
+
+    if cherry_pick_in_progress(repo)? {
+        let args = ["cherry-pick", "--abort"];
+        if !repo.git_status(&args)? {
+            return Err(Error::git_exited(&args));
+        }
+    }
+
+    let args = ["reset", "--hard", &state.orig_head];
+    if !repo.git_status(&args)? {
+        return Err(Error::git_exited(&args));
+    }
+
+    state.remove()
+}
+
+/// Cherry-pick the remaining commits one by one, stopping at the first failure
+fn run_apply(repo: &Repository, options: &PickOptions, state: &mut PickState) -> Result<()> {
+    let total = state.done.len() + state.todo.len();
+
+    while let Some(item) = state.todo.first().cloned() {
+        let progress = format!("[{}/{}]", state.done.len() + 1, total);
+        if item.action == Action::Drop {
+            eprintln!("{} Dropping {}", progress, item.hash);
+            state.advance()?;
+            continue;
+        }
+
+        let hash = repo.resolve_commit(&item.hash)?;
+        state.pre_pick_head = Some(repo.resolve_commit("HEAD")?);
+        state.save()?;
+        let args = options.cherry_pick_args(&hash, is_merge(repo, &hash)?, item.action.folds());
+        match item.action {
+            Action::Squash => eprintln!("{} Squashing {}", progress, hash),
+            Action::Fixup => eprintln!("{} Fixing up with {}", progress, hash),
+            _ => eprintln!("{} Picking {}", progress, hash),
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        if !repo.git_status(&args)? {
+            return Err(Error::PickConflict(hash));
+        }
+
+        finish_pick(repo, options, item.action, &hash)?;
+        if item.action == Action::Edit {
+            // The edited commit stays current, so --continue moves on from it
+            state.picked_head = Some(repo.resolve_commit("HEAD")?);
+            state.save()?;
+            eprintln!("Stopped at {}; amend it, then run \"git bp pick --continue\"", hash);
+            return Ok(());
+        }
+        state.advance()?;
+    }
+
+    state.remove()?;
+    eprintln!("Successfully picked {} commits", total);
+    Ok(())
+}
+
+/// Amend HEAD after git cherry-pick carried out `action` for the commit `hash`, as configured
+fn finish_pick(repo: &Repository, options: &PickOptions, action: Action, hash: &str) -> Result<()> {
+    let amendment = options.amendment(repo, hash, action, true)?;
+    if amendment.is_empty() {
+        return Ok(());
+    }
+    amendment.apply(repo, hash)
+}
+
+/// Check whether git has a cherry-pick in progress
+fn cherry_pick_in_progress(repo: &Repository) -> Result<bool> {
+    Ok(repo.resolve("CHERRY_PICK_HEAD")?.is_some())
+}
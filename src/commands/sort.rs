@@ -4,10 +4,16 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 
-use std::collections::HashMap;
-use std::collections::HashSet;
-use std::process::Command;
-use crate::utils::commits::CommitsParser;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use std::rc::Rc;
+use serde::Serialize;
+use crate::error::{Error, Result};
+use crate::utils::backend::GitBackend;
+use crate::utils::commits::{CommitEntry, CommitInfo, CommitsParser};
+use crate::utils::output::{self, OutputFormat};
+use crate::utils::repo::{History, Repository};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -23,23 +29,41 @@ pub struct Args {
     #[arg(long = "in-place", short = 'i', requires = "commits_file")]
     pub in_place: bool,
 
-    /// Reference point to sort commits
+    /// Reference point to sort commits, give it several times to merge the orders on several branches
     #[arg(long = "ref", default_value = "HEAD")]
-    pub reference: String,
+    pub references: Vec<String>,
+}
+
+/// Comment put in front of commits that no reference reaches
+const UNREACHABLE_COMMENT: &str = "# WARNING: not reachable from any --ref, placed by committer date";
+
+/// JSON document printed by the sort command
+#[derive(Serialize)]
+struct SortOutput {
+    commits: Vec<CommitInfo>,
+    /// Hashes of the commits no reference reaches
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unreachable: Vec<String>,
 }
 
 /// Handle the sort command - sort commits in topological order
-pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    let repo = Repository::open()?;
+
     // Get commit list from either command line args or file
     let (commit_infos, file_path) = CommitsParser::get_commits(args.commits, args.commits_file)?;
 
     // Extract hashes for sorting
     let commit_hashes = CommitsParser::extract_hashes(&commit_infos);
-    let sorted_hashes = sort_commits_topologically(commit_hashes, &args.reference)?;
+    let sorted = sort_commits_topologically(&repo, commit_hashes, &args.references)?;
+    let sorted_hashes = &sorted.hashes;
+    if !sorted.unreachable.is_empty() {
+        warn_unreachable(&repo, &sorted.unreachable, &args.references)?;
+    }
 
     // Create sorted CommitInfo vector, preserving original Change-Id and title information
     let mut sorted_commits_info = Vec::new();
-    for sorted_hash in &sorted_hashes {
+    for sorted_hash in sorted_hashes {
         if let Some(original_info) = commit_infos.iter().find(|info|
             info.hash.starts_with(sorted_hash) || sorted_hash.starts_with(&info.hash)) {
             let mut new_info = original_info.clone();
@@ -70,12 +94,16 @@ pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
                 }
 
                 // Sort entries by the order in sorted_commits_info, keeping comments with commits
-                let mut sorted_entries = entries.clone();
-                sorted_entries.sort_by(|a, b| {
-                    let a_idx = sorted_map.get(&a.commit.hash).unwrap_or(&999999);
-                    let b_idx = sorted_map.get(&b.commit.hash).unwrap_or(&999999);
-                    a_idx.cmp(b_idx)
-                });
+                // and squashes and fixups right after the commit they are folded into
+                let mut groups: Vec<Vec<CommitEntry>> = Vec::new();
+                for entry in entries {
+                    match groups.last_mut() {
+                        Some(group) if entry.action().folds() => group.push(entry),
+                        _ => groups.push(vec![entry]),
+                    }
+                }
+                groups.sort_by_key(|group| sorted_map.get(&group[0].commit.hash).copied().unwrap_or(usize::MAX));
+                let mut sorted_entries: Vec<CommitEntry> = groups.into_iter().flatten().collect();
 
                 // Update entries with enriched commit information
                 for entry in &mut sorted_entries {
@@ -84,76 +112,346 @@ pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
                         entry.commit.hash.starts_with(&c.hash)) {
                         entry.commit = sorted_commit.clone();
                     }
+                    // Flag commits no reference reaches, once, and drop the flag once they are reachable
+                    let unreachable = sorted.unreachable.contains(&entry.commit.hash);
+                    entry.comments.retain(|comment| comment != UNREACHABLE_COMMENT);
+                    if unreachable {
+                        entry.comments.push(UNREACHABLE_COMMENT.to_string());
+                    }
                 }
 
-                CommitsParser::write_to_file(&file_path, &modelines, &sorted_entries)?;
-                println!("Updated {} commits in {}", sorted_commits_info.len(), file_path);
+                CommitsParser::write_to_file(&repo, &file_path, &modelines, &sorted_entries)?;
+                match format {
+                    OutputFormat::Text => println!("Updated {} commits in {}", sorted_commits_info.len(), file_path),
+                    OutputFormat::Json => print_commits(&repo, &sorted_commits_info, &sorted.unreachable, format)?,
+                }
             } else {
                 // Print to stdout
-                for commit in &sorted_commits_info {
-                    println!("{}", commit.to_line());
-                }
+                print_commits(&repo, &sorted_commits_info, &sorted.unreachable, format)?;
             }
         }
         None => {
             // CLI commits - print to stdout
-            for commit in &sorted_commits_info {
+            print_commits(&repo, &sorted_commits_info, &sorted.unreachable, format)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Print sorted commits to stdout in the requested format
+fn print_commits(repo: &impl GitBackend, commits: &[CommitInfo], unreachable: &[String], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            for commit in commits {
+                if unreachable.contains(&commit.hash) {
+                    println!("{}", UNREACHABLE_COMMENT);
+                }
                 println!("{}", commit.to_line());
             }
+            Ok(())
+        }
+        OutputFormat::Json => {
+            // Resolve every commit so consumers get full hashes and titles
+            let mut commits = commits.to_vec();
+            for commit in &mut commits {
+                commit.fetch_change_id_if_missing(repo)?;
+                commit.fetch_title_if_missing(repo)?;
+            }
+            output::print_json(&SortOutput {
+                commits,
+                unreachable: unreachable.to_vec(),
+            })
         }
     }
+}
 
+/// Warn about commits that are not reachable from any reference
+fn warn_unreachable(repo: &impl GitBackend, unreachable: &[String], references: &[String]) -> Result<()> {
+    eprintln!("WARNING: {} commit(s) are not reachable from {}, placed by committer date:",
+              unreachable.len(), references.join(", "));
+    for hash in unreachable {
+        eprintln!("  {} {}", hash, repo.title(hash)?.unwrap_or_default());
+    }
     Ok(())
 }
 
-fn sort_commits_topologically(
+/// Commits sorted by [`sort_commits_topologically`]
+pub struct SortedCommits {
+    /// Commit hashes as given, oldest first
+    pub hashes: Vec<String>,
+    /// Hashes, as given, of the commits no reference reaches, oldest first
+    pub unreachable: Vec<String>,
+}
+
+/// Sort commit hashes in topological order, oldest first like `git rev-list --reverse --topo-order`
+///
+/// This is the order every commits file is in, the one commits are picked in.
+/// The orders on several references are merged by committer date: going from
+/// the newest commit back, the next commit is the newest of those coming next
+/// on their first reference, as long as no commit descending from it on any
+/// reference is left. Commits no reference reaches are placed by committer
+/// date as well.
+pub fn sort_commits_topologically(
+    repo: &impl GitBackend,
     input_commits: Vec<String>,
-    reference: &str,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let output = Command::new("git")
-        .args(["rev-list", "--topo-order", reference])
-        .output()?;
+    references: &[String],
+) -> Result<SortedCommits> {
+    // Resolve what git can; abbreviations naming several commits are looked up on the references
+    let mut resolved = Vec::new();
+    for input in input_commits {
+        resolved.push((repo.resolve(&input)?, input));
+    }
+    let known: Vec<String> = resolved.iter().filter_map(|(hash, _)| hash.clone()).collect();
+    // An abbreviation git could not resolve may name a commit older than what the others need
+    let ambiguous = known.len() < resolved.len();
+    let histories = references.iter()
+        .map(|reference| if ambiguous {
+            repo.history(reference)
+        } else {
+            sorting_range(repo, &known, reference)
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("git rev-list: {}", stderr).into());
+    // Drop inputs naming a commit listed already
+    let mut prefix_maps: Option<Vec<PrefixMap>> = None;
+    let mut commits: Vec<(String, String)> = Vec::new();
+    for (hash, input) in resolved {
+        let hash = match hash {
+            Some(hash) => hash,
+            None => {
+                let prefix_maps = prefix_maps.get_or_insert_with(|| histories.iter().map(|history| PrefixMap::new(history)).collect());
+                lookup_prefix(prefix_maps, &input)?
+            }
+        };
+        if !commits.iter().any(|(_, listed)| *listed == hash) {
+            commits.push((input, hash));
+        }
     }
 
-    let all_commits = String::from_utf8_lossy(&output.stdout);
-    let commit_list: Vec<&str> = all_commits.lines().collect();
+    // The first reference reaching each commit, and the commit's position on it
+    let homes: Vec<Option<(usize, usize)>> = commits.iter()
+        .map(|(_, hash)| histories.iter()
+            .enumerate()
+            .find_map(|(i, history)| history.position(hash).map(|position| (i, position))))
+        .collect();
+    let reachable: Vec<usize> = (0..commits.len()).filter(|&i| homes[i].is_some()).collect();
 
-    let mut input_set: HashSet<String> = input_commits.into_iter().collect();
+    // A commit can only be an ancestor of another if every reference reaching the
+    // descendant reaches it too, so checking on the descendant's home is enough
+    let descends: Vec<Vec<bool>> = (0..commits.len())
+        .map(|descendant| (0..commits.len())
+            .map(|ancestor| match homes[descendant] {
+                Some((home, position)) if ancestor != descendant => {
+                    let history = &histories[home];
+                    history.position(&commits[ancestor].1)
+                        .is_some_and(|ancestor| history.is_ancestor_at(ancestor, position))
+                }
+                _ => false,
+            })
+            .collect())
+        .collect();
+    let mut descendants: HashMap<usize, usize> = reachable.iter()
+        .map(|&i| (i, reachable.iter().filter(|&&j| descends[j][i]).count()))
+        .collect();
+    let mut times = HashMap::new();
+    for &i in &reachable {
+        times.insert(i, repo.commit_time(&commits[i].1)?);
+    }
 
-    let mut sorted_commits = Vec::new();
-    for commit in commit_list {
-        let commit = commit.trim();
-        if commit.is_empty() {
-            continue;
+    // Repeatedly take the newest of the commits ranking first on their reference among
+    // those whose descendants were all taken, so independent commits interleave by date
+    let mut sorted = Vec::with_capacity(reachable.len());
+    loop {
+        let mut heads: HashMap<usize, (usize, usize)> = HashMap::new();
+        for (&i, _) in descendants.iter().filter(|(_, &count)| count == 0) {
+            let (home, position) = homes[i].unwrap();
+            let rank = histories[home].rank_at(position);
+            if heads.get(&home).is_none_or(|&(head_rank, _)| rank < head_rank) {
+                heads.insert(home, (rank, i));
+            }
         }
+        let Some(next) = heads.into_iter()
+            .min_by_key(|&(home, (_, i))| (Reverse(times[&i]), home))
+            .map(|(_, (_, i))| i) else {
+            break;
+        };
 
-        if input_set.is_empty() {
-            continue;
+        descendants.remove(&next);
+        for (&i, count) in descendants.iter_mut() {
+            if descends[next][i] {
+                *count -= 1;
+            }
         }
+        sorted.push(next);
+    }
 
-        let mut matched_input = None;
-        for input_commit in input_set.iter() {
-            if commit.starts_with(input_commit.as_str()) || input_commit.starts_with(commit) {
-                matched_input = Some(input_commit.clone());
-                break;
-            }
+    let mut unreachable = Vec::new();
+    for i in (0..commits.len()).filter(|&i| homes[i].is_none()) {
+        unreachable.push((repo.commit_time(&commits[i].1)?, i));
+    }
+    if unreachable.is_empty() {
+        return Ok(SortedCommits {
+            hashes: sorted.into_iter().rev().map(|i| commits[i].0.clone()).collect(),
+            unreachable: Vec::new(),
+        });
+    }
+
+    // Slot the unreachable commits in front of the first sorted commit that is older
+    unreachable.sort_by_key(|&(time, _)| Reverse(time));
+    let mut order = Vec::with_capacity(commits.len());
+    let mut pending = unreachable.iter().peekable();
+    for i in sorted {
+        let time = times[&i];
+        while let Some(&(_, unplaced)) = pending.next_if(|(unplaced_time, _)| *unplaced_time > time) {
+            order.push(unplaced);
         }
+        order.push(i);
+    }
+    order.extend(pending.map(|&(_, i)| i));
 
-        if let Some(matched) = matched_input {
-            sorted_commits.push(matched.clone());
-            input_set.remove(&matched); // 移除已找到的提交，避免重复
+    Ok(SortedCommits {
+        hashes: order.into_iter().rev().map(|i| commits[i].0.clone()).collect(),
+        unreachable: unreachable.into_iter().rev().map(|(_, i)| commits[i].0.clone()).collect(),
+    })
+}
+
+/// Load the part of a reference's history holding every commit in `hashes` that is on it
+///
+/// The commits on the reference all descend from the merge base of the commits
+/// and the reference, so only the range from there up to the reference is
+/// walked, however deep in history the commits sit.
+fn sorting_range(repo: &impl GitBackend, hashes: &[String], reference: &str) -> Result<Rc<History>> {
+    let mut base = repo.resolve(reference)?.ok_or_else(|| Error::CommitNotFound(reference.to_string()))?;
+    for hash in hashes {
+        match repo.merge_base(&base, hash)? {
+            Some(merge_base) => base = merge_base,
+            // A commit sharing no history with the base could still be merged in from another root
+            None => return repo.history(reference),
+        }
+    }
+    repo.history_since(&base, reference)
+}
+
+/// The commits of a history by full hash, in order, so abbreviations are looked up with a range query
+struct PrefixMap<'a> {
+    hashes: BTreeSet<&'a str>,
+}
+
+impl<'a> PrefixMap<'a> {
+    fn new(history: &'a History) -> Self {
+        Self {
+            hashes: history.commits().iter().map(|commit| commit.hash.as_str()).collect(),
+        }
+    }
+
+    /// Full hashes starting with `prefix`
+    fn lookup<'b>(&'b self, prefix: &'b str) -> impl Iterator<Item = &'a str> + 'b {
+        self.hashes.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .copied()
+            .take_while(move |hash| hash.starts_with(prefix))
+    }
+}
+
+/// Find the one commit on the references an abbreviated hash names
+fn lookup_prefix(prefix_maps: &[PrefixMap], abbrev: &str) -> Result<String> {
+    let prefix = abbrev.to_ascii_lowercase();
+    let mut candidates: Vec<String> = Vec::new();
+    for prefix_map in prefix_maps {
+        for hash in prefix_map.lookup(&prefix) {
+            if !candidates.iter().any(|candidate| candidate == hash) {
+                candidates.push(hash.to_string());
+            }
         }
     }
 
-    // 检查是否所有输入的提交都被找到
-    if !input_set.is_empty() {
-        let missing: Vec<_> = input_set.iter().collect();
-        return Err(format!("Some commits not found in HEAD history: {:?}", missing).into());
+    match candidates.len() {
+        0 => Err(Error::CommitNotFound(abbrev.to_string())),
+        1 => Ok(candidates.remove(0)),
+        _ => Err(Error::AmbiguousHash {
+            hash: abbrev.to_string(),
+            candidates,
+        }),
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fake::FakeRepo;
+
+    fn refs(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn sorts_oldest_first_with_abbreviated_hashes() {
+        let mut repo = FakeRepo::new();
+        let root = repo.commit(&[], "root");
+        let a = repo.commit(&[&root], "a");
+        let side = repo.commit(&[&root], "side");
+        let b = repo.commit(&[&a], "b");
+        let merge = repo.commit(&[&b, &side], "merge");
+        repo.set_ref("up", &merge);
+
+        let input = vec![a[..12].to_string(), merge.clone(), b.clone()];
+        let sorted = sort_commits_topologically(&repo, input, &refs(&["up"])).unwrap();
+        assert_eq!(sorted.hashes, vec![a[..12].to_string(), b, merge]);
+        assert!(sorted.unreachable.is_empty());
+    }
+
+    #[test]
+    fn merges_orders_of_several_references() {
+        let mut repo = FakeRepo::new();
+        let root = repo.commit(&[], "root");
+        let forked = repo.commit(&[&root], "forked");
+        let mainline = repo.commit(&[&forked], "mainline feature");
+        let stable = repo.commit(&[&forked], "stable fix");
+        let elsewhere = repo.commit(&[&root], "elsewhere");
+        let newest = repo.commit(&[&mainline], "mainline fix");
+        repo.set_ref("main", &newest);
+        repo.set_ref("stable", &stable);
+
+        let input = vec![forked.clone(), elsewhere.clone(), stable.clone(), newest.clone(), mainline.clone()];
+        let sorted = sort_commits_topologically(&repo, input, &refs(&["main", "stable"])).unwrap();
+        // The stable fix was made after the mainline feature and both descend from
+        // the forked commit; the unreachable commit goes between the commits made
+        // before and after it
+        assert_eq!(sorted.hashes, vec![forked, mainline, stable, elsewhere.clone(), newest]);
+        assert_eq!(sorted.unreachable, vec![elsewhere]);
 
-    Ok(sorted_commits)
+        assert!(sort_commits_topologically(&repo, vec!["f00".to_string()], &refs(&["main"])).is_err());
+    }
+
+    #[test]
+    fn looks_up_abbreviations_on_references_deep_in_history() {
+        let mut repo = FakeRepo::new();
+        let root = repo.commit(&[], "root");
+        let mut chain = vec![root.clone()];
+        for i in 0..50 {
+            let parent = chain.last().unwrap().clone();
+            chain.push(repo.commit(&[&parent], &format!("upstream {}", i)));
+        }
+        repo.set_ref("up", chain.last().unwrap());
+
+        // Make a commit off the reference sharing a two digit prefix with just one of the first upstream commits
+        let prefix = loop {
+            let elsewhere = repo.commit(&[&root], "elsewhere");
+            let prefix = &elsewhere[..2];
+            if chain[1..10].iter().any(|hash| hash.starts_with(prefix))
+                && chain.iter().filter(|hash| hash.starts_with(prefix)).count() == 1 {
+                break prefix.to_string();
+            }
+        };
+        assert_eq!(repo.resolve(&prefix).unwrap(), None);
+
+        let input = vec![root.clone(), prefix.clone(), chain[40].clone()];
+        let sorted = sort_commits_topologically(&repo, input, &refs(&["up"])).unwrap();
+        assert_eq!(sorted.hashes, vec![root, prefix.clone(), chain[40].clone()]);
+
+        // The commits git resolves alone all descend from the one the abbreviation names
+        let input = vec![prefix.clone(), chain[45].clone(), chain[40].clone()];
+        let sorted = sort_commits_topologically(&repo, input, &refs(&["up"])).unwrap();
+        assert_eq!(sorted.hashes, vec![prefix, chain[40].clone(), chain[45].clone()]);
+    }
 }
@@ -0,0 +1,201 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use log::debug;
+use serde::Serialize;
+use crate::error::Result;
+use crate::commands::fix::{self, FoundBy, FoundOriginal};
+use crate::utils::backend::GitBackend;
+use crate::utils::commits::CommitInfo;
+use crate::utils::output::{self, OutputFormat};
+use crate::utils::repo::Repository;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Base commit the backport branch started from (exclusive)
+    #[arg(long = "base", required = true)]
+    pub base: String,
+
+    /// Upstream branch the commits were backported from
+    #[arg(long = "ref", required = true)]
+    pub ref_branch: String,
+}
+
+/// Backport state of every commit in base..HEAD
+#[derive(Serialize)]
+struct StatusReport {
+    commits: Vec<CommitStatus>,
+    summary: Summary,
+}
+
+/// A commit in base..HEAD and its originals on the upstream branch
+#[derive(Serialize)]
+struct CommitStatus {
+    #[serde(flatten)]
+    commit: CommitInfo,
+    originals: Vec<Original>,
+    /// Whether no upstream original was found, i.e. the commit only exists downstream
+    downstream_only: bool,
+}
+
+/// An upstream original of a backported commit
+#[derive(Serialize)]
+struct Original {
+    hash: String,
+    found_by: FoundBy,
+}
+
+/// Number of commits in each state
+#[derive(Serialize)]
+struct Summary {
+    total: usize,
+    backported: usize,
+    downstream_only: usize,
+    /// Backported commits by how their (first) original was found
+    by_method: BTreeMap<FoundBy, usize>,
+}
+
+/// Handle the status command - report which commits in base..HEAD came from upstream
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    let repo = Repository::open()?;
+
+    let commits_in_range = fix::get_commits_in_range(&repo, &args.base, "HEAD")?;
+    debug!("Found {} commits in range {}..HEAD", commits_in_range.len(), args.base);
+
+    let mut commits = Vec::new();
+    for mut commit in commits_in_range {
+        commit.fetch_change_id_if_missing(&repo)?;
+        commit.fetch_title_if_missing(&repo)?;
+
+        let mut originals = fix::find_all_original_commits(&repo, &commit, &args.ref_branch)?;
+
+        // Commits picked with -x name their original
+        for hash in fix::find_cherry_picked_originals(&repo, &commit.hash, &args.ref_branch)? {
+            add_original(&mut originals, &commit, hash, FoundBy::CherryPick);
+        }
+
+        commits.push((commit, originals));
+    }
+
+    // Commits applied with `git am` or picked without -x can only be recognized by their diff,
+    // which is only computed for those nothing else matched
+    let unresolved: Vec<String> = commits.iter()
+        .filter(|(_, originals)| originals.is_empty())
+        .map(|(commit, _)| commit.hash.clone())
+        .collect();
+    if !unresolved.is_empty() {
+        debug!("Matching {} commits by patch-id", unresolved.len());
+        repo.load_patch_ids(&unresolved)?;
+        let upstream_patch_ids = upstream_patch_ids(&repo, &unresolved, &args.base, &args.ref_branch)?;
+        for (commit, originals) in commits.iter_mut().filter(|(_, originals)| originals.is_empty()) {
+            if let Some(original) = repo.patch_id(&commit.hash)?.and_then(|patch_id| upstream_patch_ids.get(&patch_id)) {
+                add_original(originals, commit, original.clone(), FoundBy::PatchId);
+            }
+        }
+    }
+
+    let commits: Vec<CommitStatus> = commits.into_iter()
+        .map(|(commit, originals)| CommitStatus {
+            downstream_only: originals.is_empty(),
+            originals: originals.into_iter()
+                .map(|original| Original {
+                    hash: original.hash,
+                    found_by: original.found_by,
+                })
+                .collect(),
+            commit,
+        })
+        .collect();
+
+    let mut summary = Summary {
+        total: commits.len(),
+        backported: 0,
+        downstream_only: 0,
+        by_method: BTreeMap::new(),
+    };
+    for status in &commits {
+        match status.originals.first() {
+            Some(original) => {
+                summary.backported += 1;
+                *summary.by_method.entry(original.found_by).or_default() += 1;
+            }
+            None => summary.downstream_only += 1,
+        }
+    }
+
+    let report = StatusReport { commits, summary };
+    match format {
+        OutputFormat::Text => print_report(&repo, &report, &args.ref_branch),
+        OutputFormat::Json => output::print_json(&report)?,
+    }
+
+    Ok(())
+}
+
+/// Compute the patch-ids of the upstream commits that could share a diff with the given commits
+///
+/// A commit with the same diff changes the same files, so only the commits in
+/// `base..ref_branch` touching a file one of the given commits changes are
+/// considered. Each patch-id maps to the oldest commit having it.
+fn upstream_patch_ids(repo: &Repository, hashes: &[String], base: &str, ref_branch: &str) -> Result<HashMap<String, String>> {
+    let mut paths: Vec<String> = Vec::new();
+    for hash in hashes {
+        paths.extend(repo.changed_files(hash)?);
+    }
+    paths.sort();
+    paths.dedup();
+
+    let mut patch_ids = HashMap::new();
+    if paths.is_empty() {
+        return Ok(patch_ids);
+    }
+    let candidates = repo.commits_touching(base, ref_branch, &paths)?;
+    debug!("{} upstream commits touch the {} files changed by unmatched commits", candidates.len(), paths.len());
+    repo.load_patch_ids(&candidates)?;
+    for hash in candidates {
+        if let Some(patch_id) = repo.patch_id(&hash)? {
+            patch_ids.entry(patch_id).or_insert(hash);
+        }
+    }
+    Ok(patch_ids)
+}
+
+/// Record an original of a commit unless it was already found another way
+fn add_original(originals: &mut Vec<FoundOriginal>, commit: &CommitInfo, hash: String, found_by: FoundBy) {
+    if !originals.iter().any(|original| original.hash == hash) {
+        debug!("Found original {} of {} by {:?}", hash, commit.hash, found_by);
+        originals.push(FoundOriginal { hash, found_by });
+    }
+}
+
+/// Print one line per commit with its upstream original, followed by the summary
+fn print_report(repo: &Repository, report: &StatusReport, ref_branch: &str) {
+    let object_format = repo.object_format();
+    let width = object_format.display_abbrev_len();
+
+    for status in &report.commits {
+        let hash = object_format.abbreviate(&status.commit.hash);
+        let title = status.commit.title.as_deref().unwrap_or("");
+        if status.downstream_only {
+            println!("{}  {:<width$}  {:<13}  {}", hash, "-", "downstream", title);
+            continue;
+        }
+        for (i, original) in status.originals.iter().enumerate() {
+            let (hash, title) = if i == 0 { (hash, title) } else { ("", "") };
+            println!("{:<width$}  {}  {:<13}  {}", hash, object_format.abbreviate(&original.hash),
+                     original.found_by.name(), title);
+        }
+    }
+
+    let summary = &report.summary;
+    println!();
+    println!("{} commit(s): {} backported from {}, {} downstream-only",
+             summary.total, summary.backported, ref_branch, summary.downstream_only);
+    for (found_by, count) in &summary.by_method {
+        println!("  {:<13}  {}", found_by.name(), count);
+    }
+}
@@ -6,6 +6,8 @@
 
 use std::fs;
 use std::path::Path;
+use crate::error::{Error, Result};
+use crate::utils::output::OutputFormat;
 
 // Embed the vim plugin file content at compile time
 const VIM_PLUGIN_CONTENT: &str = include_str!("../../vim/ftplugin/gitbackportcommits.vim");
@@ -22,14 +24,19 @@ pub struct Args {
 }
 
 /// Handle the vim command - install vim syntax support files
-pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+pub fn command(args: Args, format: OutputFormat) -> Result<()> {
+    // Nothing is printed, so there is no report to print as JSON
+    if format != OutputFormat::Text {
+        return Err(Error::JsonNotSupported("by vim"));
+    }
+
     if let Some(dir) = args.vim_dir {
         // Install to user-specified directory
         return install_to_vim_dir(&dir, args.force);
     }
 
     // Install to default vim and neovim directories
-    let home = std::env::var("HOME")?;
+    let home = std::env::var("HOME").map_err(|_| Error::HomeNotSet)?;
 
     // Try neovim first (respect XDG_CONFIG_HOME)
     let nvim_dir = get_neovim_config_dir()?;
@@ -42,19 +49,19 @@ pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
 }
 
 /// Get neovim configuration directory, respecting XDG_CONFIG_HOME
-fn get_neovim_config_dir() -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+fn get_neovim_config_dir() -> Result<std::path::PathBuf> {
     if let Ok(xdg_config_home) = std::env::var("XDG_CONFIG_HOME") {
         // Use XDG_CONFIG_HOME if set
         Ok(Path::new(&xdg_config_home).join("nvim"))
     } else {
         // Fall back to default ~/.config/nvim
-        let home = std::env::var("HOME")?;
+        let home = std::env::var("HOME").map_err(|_| Error::HomeNotSet)?;
         Ok(Path::new(&home).join(".config").join("nvim"))
     }
 }
 
 /// Install vim plugin to a specific vim configuration directory
-fn install_to_vim_dir(vim_config_dir: &str, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+fn install_to_vim_dir(vim_config_dir: &str, force: bool) -> Result<()> {
     let target_file = Path::new(vim_config_dir).join("ftplugin").join("gitbackportcommits.vim");
 
     // Check if file exists and --force is not used
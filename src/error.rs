@@ -0,0 +1,153 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::process::Output;
+
+/// Result of the operations of this crate
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while working with backports
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A git command exited unsuccessfully, `stderr` is empty when git wrote to the terminal
+    #[error("{}", describe_git_failure(.args, .stderr))]
+    GitFailed {
+        args: Vec<String>,
+        stderr: String,
+    },
+    /// A revision does not name any commit
+    #[error("Unknown revision: {0}")]
+    CommitNotFound(String),
+    /// An abbreviated hash names more than one commit
+    #[error("Ambiguous commit hash {hash}, it could be {}", .candidates.join(", "))]
+    AmbiguousHash {
+        hash: String,
+        candidates: Vec<String>,
+    },
+    /// A line of a commits file is not in the commits file format
+    #[error("Cannot parse line: {line}")]
+    ParseError {
+        line: String,
+    },
+    /// A commits file lists no commits
+    #[error("No commits found in {0}")]
+    NoCommits(String),
+    /// A commits file has problems `git bp lint` could not fix
+    #[error("{count} problem(s) found in {file}")]
+    LintProblems {
+        count: usize,
+        file: String,
+    },
+    /// Two revisions do not share any history
+    #[error("{0} and {1} have no common ancestor")]
+    NoCommonAncestor(String, String),
+    /// A revision range is not of the form `<base>..<tip>`
+    #[error("Invalid range {0:?}, expected <base>..<tip>")]
+    InvalidRange(String),
+    /// A git config key has a value git-bp cannot use
+    #[error("Invalid {key} {value:?}, expected {expected}")]
+    InvalidConfig {
+        key: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+    /// The repository uses an object format git-bp does not know
+    #[error("Unsupported object format: {0}")]
+    UnsupportedObjectFormat(String),
+    /// git printed something git-bp cannot read
+    #[error("Invalid output of git {}: {output}", .args.join(" "))]
+    InvalidGitOutput {
+        args: Vec<String>,
+        output: String,
+    },
+    /// A hunk header of a diff printed by git has no line numbers
+    #[error("Invalid hunk header in {rev}: {line}")]
+    InvalidHunkHeader {
+        rev: String,
+        line: String,
+    },
+    /// A pipe to or from a git subprocess was not set up
+    #[error("git {command} has no {stream}")]
+    MissingPipe {
+        command: String,
+        stream: &'static str,
+    },
+    /// `--fold` was given an action that does not fold a commit into HEAD
+    #[error("--fold only accepts squash or fixup, not {0}")]
+    InvalidFold(&'static str),
+    /// `--format json` was given to a command, or a mode of one, that prints no report
+    #[error("--format json is not supported {0}")]
+    JsonNotSupported(&'static str),
+    /// A `pick --apply` or `pick --rebase` was started while a pick is in progress
+    #[error("A pick is already in progress; use --continue, --skip or --abort")]
+    PickInProgress,
+    /// `pick --continue`, `--skip` or `--abort` was run without a pick in progress
+    #[error("No pick in progress")]
+    NoPickInProgress,
+    /// A commit of a `pick --apply` run did not apply cleanly
+    #[error("Could not apply {0}; resolve the conflict and run \"git bp pick --continue\", \
+             or use \"git bp pick --skip\" / \"git bp pick --abort\"")]
+    PickConflict(String),
+    /// The `git rebase -i` run by `pick --rebase` stopped early
+    #[error("The rebase stopped before all commits were picked; resolve the problem and run \
+             \"git rebase --continue\", or use \"git rebase --skip\" / \"git rebase --abort\"")]
+    RebaseStopped,
+    /// A squash or fixup comes before any commit it could be folded into
+    #[error("Cannot {action} {hash} without a previous commit to fold it into")]
+    NothingToFold {
+        action: &'static str,
+        hash: String,
+    },
+    /// The home directory to install into is not known
+    #[error("HOME is not set")]
+    HomeNotSet,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// The repository could not be read in-process
+    #[error(transparent)]
+    Gix(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// Describe a git command that exited unsuccessfully
+    pub fn git_failed<S: AsRef<str>>(args: &[S], output: &Output) -> Self {
+        Self::GitFailed {
+            args: args.iter().map(|arg| arg.as_ref().to_string()).collect(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+    }
+
+    /// Describe a git command that exited unsuccessfully after writing its errors to the terminal
+    pub fn git_exited<S: AsRef<str>>(args: &[S]) -> Self {
+        Self::GitFailed {
+            args: args.iter().map(|arg| arg.as_ref().to_string()).collect(),
+            stderr: String::new(),
+        }
+    }
+
+    /// Describe a git command whose input or output could not be piped
+    pub fn missing_pipe<S: AsRef<str>>(args: &[S], stream: &'static str) -> Self {
+        Self::MissingPipe {
+            command: args.iter().map(|arg| arg.as_ref()).collect::<Vec<_>>().join(" "),
+            stream,
+        }
+    }
+
+    /// Wrap an error reported by gix
+    pub fn gix<E: std::error::Error + Send + Sync + 'static>(err: E) -> Self {
+        Self::Gix(Box::new(err))
+    }
+}
+
+/// Message of [`Error::GitFailed`]
+fn describe_git_failure(args: &[String], stderr: &str) -> String {
+    match stderr.trim() {
+        "" => format!("git {} failed", args.join(" ")),
+        stderr => format!("git {} failed: {}", args.join(" "), stderr),
+    }
+}
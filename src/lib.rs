@@ -0,0 +1,20 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//! Finding, ordering and applying backports of git commits
+//!
+//! This is the library behind the `git-bp` command. [`CommitsParser`] reads
+//! and writes commits files, the fix finding engine lives in
+//! [`commands::fix`] and the sorting engine in [`commands::sort`]. Both work
+//! on any [`utils::backend::GitBackend`], such as the
+//! [`utils::repo::Repository`] of the current directory.
+
+pub mod commands;
+pub mod error;
+pub mod utils;
+
+pub use error::{Error, Result};
+pub use utils::commits::{CommitEntry, CommitInfo, CommitsParser};
@@ -5,15 +5,18 @@
  */
 
 use clap::{Parser, Subcommand};
-
-mod commands;
-mod utils;
+use git_backport::commands;
+use git_backport::utils::output::OutputFormat;
 
 #[derive(Parser)]
 #[command(name = "git-bp")]
 #[command(about = "A Git tool for backporting commits between branches")]
 #[command(version)]
 struct Cli {
+    /// Output format
+    #[arg(long = "format", global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -22,31 +25,75 @@ struct Cli {
 enum Commands {
     /// Sort commits in topological order
     Sort(commands::sort::Args),
-    /// Generate git cherry-pick commands
+    /// Generate or run git cherry-pick commands
+    ///
+    /// Picks are configured per repository with git config: bp.pick.signoff
+    /// and bp.pick.recordOrigin (both on by default) control --signoff and
+    /// -x, bp.pick.strategy sets the merge strategy, bp.pick.mainline the
+    /// parent merge commits are picked relative to, bp.pick.rewriteChangeId
+    /// enables --rewrite-change-id, and every bp.pick.trailer value is added
+    /// as a trailer like --trailer.
     Pick(commands::pick::Args),
     /// Install vim syntax support files
     Vim(commands::vim::Args),
     /// Find fixes for commits on a reference branch
     Fix(commands::fix::Args),
+    /// Manage the on-disk commit metadata cache
+    Cache(commands::cache::Args),
+    /// Show which commits were backported from a reference branch
+    Status(commands::status::Args),
+    /// List upstream commits touching our paths that were never backported
+    Missing(commands::missing::Args),
+    /// Find missing upstream commits that listed commits depend on
+    Deps(commands::deps::Args),
+    /// Show how backported commits differ from their upstream originals
+    Diff(commands::diff::Args),
+    /// Check a commits file for mistakes, optionally fixing them
+    Lint(commands::lint::Args),
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() {
     env_logger::init();
 
     let cli = Cli::parse();
+    if let Err(err) = run(cli) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
 
+/// Run the subcommand chosen on the command line
+fn run(cli: Cli) -> git_backport::Result<()> {
     match cli.command {
         Commands::Sort(args) => {
-            commands::sort::command(args)?;
+            commands::sort::command(args, cli.format)?;
         }
         Commands::Pick(args) => {
-            commands::pick::command(args)?;
+            commands::pick::command(args, cli.format)?;
         }
         Commands::Vim(args) => {
-            commands::vim::command(args)?;
+            commands::vim::command(args, cli.format)?;
         }
         Commands::Fix(args) => {
-            commands::fix::command(args)?;
+            commands::fix::command(args, cli.format)?;
+        }
+        Commands::Cache(args) => {
+            commands::cache::command(args, cli.format)?;
+        }
+        Commands::Status(args) => {
+            commands::status::command(args, cli.format)?;
+        }
+        Commands::Missing(args) => {
+            commands::missing::command(args, cli.format)?;
+        }
+        Commands::Deps(args) => {
+            commands::deps::command(args, cli.format)?;
+        }
+        Commands::Diff(args) => {
+            commands::diff::command(args, cli.format)?;
+        }
+        Commands::Lint(args) => {
+            commands::lint::command(args, cli.format)?;
         }
     }
 
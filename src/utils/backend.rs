@@ -0,0 +1,117 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::error::Result;
+use crate::utils::hash::ObjectFormat;
+use crate::utils::index::TrailerIndex;
+use crate::utils::metadata::CommitMetadata;
+use crate::utils::repo::History;
+
+/// The git queries finding fixes and sorting commits rely on
+///
+/// [`Repository`](crate::utils::repo::Repository) answers them from the
+/// repository of the current directory, while tests use an in-memory fake.
+/// Only the required methods touch git, everything else is derived from them.
+pub trait GitBackend {
+    /// Hash function the repository names its objects with
+    fn object_format(&self) -> ObjectFormat;
+
+    /// Resolve a revision to the full hash of the commit it points to, like `git rev-parse`
+    fn resolve(&self, rev: &str) -> Result<Option<String>>;
+
+    /// Get the metadata extracted from the message of a commit
+    fn metadata(&self, rev: &str) -> Result<Option<CommitMetadata>>;
+
+    /// Get every commit reachable from a tip, like `git rev-list <tip>`
+    fn history(&self, tip: &str) -> Result<Rc<History>>;
+
+    /// Get the stable patch-id of a commit, `None` for commits without a diff such as merges
+    fn patch_id(&self, rev: &str) -> Result<Option<String>>;
+
+    /// List the files a commit changes compared to its first parent, sorted
+    fn changed_files(&self, rev: &str) -> Result<Vec<String>>;
+
+    /// Get the committer date of a commit, in seconds since the epoch
+    fn commit_time(&self, rev: &str) -> Result<Option<i64>>;
+
+    /// Get the title of a commit, i.e. what `git log --format=%s` prints
+    fn title(&self, rev: &str) -> Result<Option<String>> {
+        Ok(self.metadata(rev)?.and_then(|metadata| metadata.title))
+    }
+
+    /// Check whether `commit` is an ancestor of (or equal to) `tip`
+    fn is_ancestor(&self, commit: &str, tip: &str) -> Result<bool> {
+        let Some(hash) = self.resolve(commit)? else {
+            return Ok(false);
+        };
+        Ok(self.history(tip)?.contains(&hash))
+    }
+
+    /// Find the best common ancestor of two commits, like `git merge-base`
+    fn merge_base(&self, one: &str, two: &str) -> Result<Option<String>> {
+        let (one, two) = (self.history(one)?, self.history(two)?);
+        // The common ancestor ranking first descends from no other one
+        Ok(one.commits()
+            .iter()
+            .enumerate()
+            .filter(|(_, commit)| two.contains(&commit.hash))
+            .min_by_key(|&(position, _)| one.rank_at(position))
+            .map(|(_, commit)| commit.hash.clone()))
+    }
+
+    /// Get the commits in `base..tip` and `base` itself, where `base` is an ancestor of `tip`
+    ///
+    /// Ancestry among commits descending from `base` is the same as on the
+    /// whole history of `tip`, without having to load all of it.
+    fn history_since(&self, base: &str, tip: &str) -> Result<Rc<History>> {
+        let hidden = self.history(base)?;
+        let base = self.resolve(base)?;
+        Ok(Rc::new(History::new(self.history(tip)?
+            .commits()
+            .iter()
+            .filter(|commit| !hidden.contains(&commit.hash) || base.as_ref() == Some(&commit.hash))
+            .cloned()
+            .collect())))
+    }
+
+    /// List commits in `exclude..tip`, newest first
+    fn range(&self, exclude: &str, tip: &str) -> Result<Rc<Vec<String>>> {
+        let excluded = self.history(exclude)?;
+        Ok(Rc::new(self.history(tip)?
+            .commits()
+            .iter()
+            .filter(|commit| !excluded.contains(&commit.hash))
+            .map(|commit| commit.hash.clone())
+            .collect()))
+    }
+
+    /// Get the metadata of the commits in `exclude..tip`, newest first
+    fn range_metadata(&self, exclude: &str, tip: &str) -> Result<Rc<Vec<CommitMetadata>>> {
+        let history = self.history(tip)?;
+        Ok(Rc::new(self.range(exclude, tip)?
+            .iter()
+            .filter_map(|hash| history.metadata(hash).cloned())
+            .collect()))
+    }
+
+    /// Get the trailer index of a branch, which answers what `git log --grep` used to
+    fn trailer_index(&self, tip: &str) -> Result<Rc<TrailerIndex>> {
+        Ok(Rc::new(TrailerIndex::build(self.history(tip)?, self.object_format())))
+    }
+
+    /// Get the stable patch-ids of the commits in `exclude..tip`, mapped to the commit having each
+    fn patch_ids(&self, exclude: &str, tip: &str) -> Result<Rc<HashMap<String, String>>> {
+        let mut patch_ids = HashMap::new();
+        for hash in self.range(exclude, tip)?.iter() {
+            if let Some(patch_id) = self.patch_id(hash)? {
+                patch_ids.insert(patch_id, hash.clone());
+            }
+        }
+        Ok(Rc::new(patch_ids))
+    }
+}
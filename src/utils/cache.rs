@@ -0,0 +1,420 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use crate::error::Result;
+use crate::utils::index::SegmentTrailers;
+use crate::utils::metadata::CommitMetadata;
+
+/// Bumped whenever the layout of a cache file changes, older files are rebuilt
+const CACHE_VERSION: u32 = 4;
+
+/// Commit metadata of branches kept in `.git/bp-cache/`
+///
+/// Commits are stored in segments, one file per tip under `segments/`. A
+/// segment only holds the commits that are not reachable from its bases, the
+/// tips of segments stored before it, so a branch that moved forward only adds
+/// the commits made since and branches sharing history share its segments.
+/// Files under `refs/` name the tip each ref pointed to when it was last
+/// cached, and are the only files ever rewritten. Storing segments and
+/// pruning them are kept apart by the `lock` file, see [`Cache::lock_shared`].
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// Contents of a segment file
+#[derive(Serialize, Deserialize)]
+struct Segment {
+    version: u32,
+    tip: String,
+    /// Tips of the segments holding the rest of the history of `tip`
+    bases: Vec<String>,
+    /// Commits reachable from `tip` but from none of `bases`, newest first
+    commits: Vec<CommitMetadata>,
+    /// Trailers of `commits`, so the trailer index is not rebuilt from the messages
+    trailers: SegmentTrailers,
+}
+
+/// A segment file without its commits, to follow the links between segments cheaply
+#[derive(Deserialize)]
+struct SegmentHeader {
+    version: u32,
+    bases: Vec<String>,
+    #[serde(rename = "commits")]
+    _commits: IgnoredAny,
+}
+
+/// A segment file without its commits, for assembling the trailer index of a cached branch
+#[derive(Deserialize)]
+struct SegmentTrailersView {
+    version: u32,
+    tip: String,
+    bases: Vec<String>,
+    #[serde(rename = "commits")]
+    _commits: IgnoredAny,
+    trailers: SegmentTrailers,
+}
+
+/// A shared hold on the cache lock, released when dropped
+pub struct CacheLock {
+    _file: fs::File,
+}
+
+/// Summary of a cached ref, as shown by `git bp cache stats`
+#[derive(Serialize)]
+pub struct CacheEntry {
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    pub tip: Option<String>,
+    /// Number of segments the history of the ref is stored in
+    pub segments: usize,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Directory holding the cache files
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Get the tip a ref pointed to when it was last cached
+    pub fn tip(&self, ref_name: &str) -> Option<String> {
+        let tip = fs::read_to_string(self.ref_path(ref_name)).ok()?;
+        let tip = tip.trim();
+        (!tip.is_empty() && self.has_segment(tip)).then(|| tip.to_string())
+    }
+
+    /// Tips of all cached refs, the most recently cached first
+    pub fn tips(&self) -> Result<Vec<String>> {
+        let mut refs = Vec::new();
+        for ref_name in self.ref_names()? {
+            let modified = fs::metadata(self.ref_path(&ref_name)).and_then(|metadata| metadata.modified()).ok();
+            refs.push((Reverse(modified), ref_name));
+        }
+        refs.sort();
+
+        let mut tips = Vec::new();
+        for (_, ref_name) in refs {
+            if let Some(tip) = self.tip(&ref_name) {
+                if !tips.contains(&tip) {
+                    tips.push(tip);
+                }
+            }
+        }
+        Ok(tips)
+    }
+
+    /// Remember the tip a ref points to, whose segment has to be stored already
+    pub fn set_tip(&self, ref_name: &str, tip: &str) -> Result<()> {
+        write_atomically(&self.ref_path(ref_name), format!("{}\n", tip).as_bytes())
+    }
+
+    /// Check whether the history of a tip is stored
+    pub fn has_segment(&self, tip: &str) -> bool {
+        self.segment_path(tip).exists()
+    }
+
+    /// Load the commits reachable from a stored tip, newest first
+    ///
+    /// The commits of each segment come before those of its bases, moved only
+    /// where needed to list every commit before its parents. Missing, corrupt
+    /// or outdated segments make the whole history unavailable.
+    pub fn load(&self, tip: &str) -> Option<Vec<CommitMetadata>> {
+        let mut commits = Vec::new();
+        let mut seen_segments = HashSet::new();
+        let mut pending = vec![tip.to_string()];
+        while let Some(segment_tip) = pending.pop() {
+            if !seen_segments.insert(segment_tip.clone()) {
+                continue;
+            }
+            let content = fs::read(self.segment_path(&segment_tip)).ok()?;
+            let segment: Segment = serde_json::from_slice(&content).ok()?;
+            if segment.version != CACHE_VERSION || segment.tip != segment_tip {
+                return None;
+            }
+            commits.extend(segment.commits);
+            // Bases are visited in order, the first one next
+            pending.extend(segment.bases.into_iter().rev());
+        }
+
+        // Bases may reach commits the tip does not, e.g. the newer part of a branch forked from
+        let by_hash: HashMap<&str, &CommitMetadata> = commits.iter().map(|commit| (commit.hash.as_str(), commit)).collect();
+        let mut reachable = HashSet::new();
+        let mut stack = vec![tip];
+        while let Some(hash) = stack.pop() {
+            if let Some(commit) = by_hash.get(hash) {
+                if reachable.insert(hash.to_string()) {
+                    stack.extend(commit.parents.iter().map(String::as_str));
+                }
+            }
+        }
+
+        let mut listed = HashSet::new();
+        commits.retain(|commit| reachable.contains(&commit.hash) && listed.insert(commit.hash.clone()));
+        Some(children_first(commits))
+    }
+
+    /// Load the trailers of every segment the history of a stored tip is kept in
+    ///
+    /// They also describe commits [`Self::load`] leaves out, which
+    /// [`crate::utils::index::TrailerIndex::from_segments`] skips.
+    pub fn load_trailers(&self, tip: &str) -> Option<Vec<SegmentTrailers>> {
+        let mut trailers = Vec::new();
+        let mut seen_segments = HashSet::new();
+        let mut pending = vec![tip.to_string()];
+        while let Some(segment_tip) = pending.pop() {
+            if !seen_segments.insert(segment_tip.clone()) {
+                continue;
+            }
+            let content = fs::read(self.segment_path(&segment_tip)).ok()?;
+            let segment: SegmentTrailersView = serde_json::from_slice(&content).ok()?;
+            if segment.version != CACHE_VERSION || segment.tip != segment_tip {
+                return None;
+            }
+            trailers.push(segment.trailers);
+            pending.extend(segment.bases);
+        }
+        Some(trailers)
+    }
+
+    /// Store the commits reachable from `tip` but from none of `bases`, whose segments have to be stored already
+    pub fn store(&self, tip: &str, bases: &[String], commits: &[CommitMetadata], trailers: &SegmentTrailers) -> Result<()> {
+        #[derive(Serialize)]
+        struct SegmentView<'a> {
+            version: u32,
+            tip: &'a str,
+            bases: &'a [String],
+            commits: &'a [CommitMetadata],
+            trailers: &'a SegmentTrailers,
+        }
+
+        let content = serde_json::to_vec(&SegmentView {
+            version: CACHE_VERSION,
+            tip,
+            bases,
+            commits,
+            trailers,
+        })?;
+        write_atomically(&self.segment_path(tip), &content)
+    }
+
+    /// Take the cache lock shared, blocking while another run prunes
+    ///
+    /// Hold it from picking the bases of a new segment until the ref naming
+    /// the segment is written, so [`Self::prune`] never removes a segment a
+    /// ref is about to need. Runs storing segments don't block each other.
+    pub fn lock_shared(&self) -> Result<CacheLock> {
+        let file = self.open_lock()?;
+        file.lock_shared()?;
+        Ok(CacheLock { _file: file })
+    }
+
+    /// Remove the segments no cached ref needs anymore, e.g. after a branch was rewritten
+    ///
+    /// Ref files not naming a stored tip, like those of older cache versions,
+    /// go as well. Waits for runs holding [`Self::lock_shared`] to finish, so
+    /// the lock must not be held by the caller.
+    pub fn prune(&self) -> Result<()> {
+        let lock = self.open_lock()?;
+        lock.lock()?;
+
+        for ref_name in self.ref_names()? {
+            if self.tip(&ref_name).is_none() {
+                fs::remove_file(self.ref_path(&ref_name))?;
+            }
+        }
+
+        let mut needed = HashSet::new();
+        let mut pending = self.tips()?;
+        while let Some(tip) = pending.pop() {
+            if !needed.insert(tip.clone()) {
+                continue;
+            }
+            if let Some(header) = self.header(&tip) {
+                pending.extend(header.bases);
+            }
+        }
+
+        let segments = self.dir.join("segments");
+        if !segments.exists() {
+            return Ok(());
+        }
+        for entry in fs::read_dir(&segments)? {
+            let path = entry?.path();
+            // Leave files other runs are writing alone
+            if path.to_string_lossy().contains(".tmp.") {
+                continue;
+            }
+            let needed = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|tip| needed.contains(tip) && path.extension().is_some_and(|ext| ext == "json"));
+            if !needed {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove every cache file
+    pub fn clear(&self) -> Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    /// Summarize every cached ref, sorted by ref name
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+        for ref_name in self.ref_names()? {
+            let tip = self.tip(&ref_name);
+            let mut segments = HashSet::new();
+            let mut pending: Vec<String> = tip.iter().cloned().collect();
+            while let Some(segment_tip) = pending.pop() {
+                if let Some(header) = self.header(&segment_tip) {
+                    if segments.insert(segment_tip) {
+                        pending.extend(header.bases);
+                    }
+                }
+            }
+            entries.push(CacheEntry {
+                ref_name,
+                tip,
+                segments: segments.len(),
+            });
+        }
+
+        entries.sort_by(|a, b| a.ref_name.cmp(&b.ref_name));
+        Ok(entries)
+    }
+
+    /// Total size of the cache files in bytes
+    pub fn size(&self) -> Result<u64> {
+        let mut files = Vec::new();
+        collect_files(&self.dir, &mut files)?;
+        let mut size = 0;
+        for path in files {
+            size += fs::metadata(&path)?.len();
+        }
+        Ok(size)
+    }
+
+    /// Read the links of a segment, ignoring missing, corrupt or outdated files
+    fn header(&self, tip: &str) -> Option<SegmentHeader> {
+        let content = fs::read(self.segment_path(tip)).ok()?;
+        let header: SegmentHeader = serde_json::from_slice(&content).ok()?;
+        (header.version == CACHE_VERSION).then_some(header)
+    }
+
+    /// Open the lock file, creating the cache directory if needed
+    fn open_lock(&self) -> Result<fs::File> {
+        fs::create_dir_all(&self.dir)?;
+        Ok(fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join("lock"))?)
+    }
+
+    /// Names of all cached refs
+    fn ref_names(&self) -> Result<Vec<String>> {
+        let refs = self.dir.join("refs");
+        let mut files = Vec::new();
+        collect_files(&refs, &mut files)?;
+        Ok(files.iter()
+            .filter_map(|path| path.strip_prefix(&self.dir).ok()?.to_str().map(str::to_string))
+            .filter(|name| !name.contains(".tmp."))
+            .collect())
+    }
+
+    /// Path of the file naming the cached tip of a ref, e.g. `refs/heads/main`
+    fn ref_path(&self, ref_name: &str) -> PathBuf {
+        self.dir.join(ref_name)
+    }
+
+    /// Path of the segment file of a tip
+    fn segment_path(&self, tip: &str) -> PathBuf {
+        self.dir.join("segments").join(format!("{}.json", tip))
+    }
+}
+
+/// Reorder commits so that each comes before its parents, keeping the given order where it already does
+///
+/// A segment's commits can descend from commits of a later base when its
+/// bases are not related to each other.
+fn children_first(commits: Vec<CommitMetadata>) -> Vec<CommitMetadata> {
+    let index: HashMap<&str, usize> = commits.iter()
+        .enumerate()
+        .map(|(i, commit)| (commit.hash.as_str(), i))
+        .collect();
+    let mut children = vec![0; commits.len()];
+    for commit in &commits {
+        for parent in &commit.parents {
+            if let Some(&parent) = index.get(parent.as_str()) {
+                children[parent] += 1;
+            }
+        }
+    }
+
+    // Always take the earliest commit whose children were all taken
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..commits.len())
+        .filter(|&i| children[i] == 0)
+        .map(Reverse)
+        .collect();
+    let mut order = Vec::with_capacity(commits.len());
+    while let Some(Reverse(i)) = ready.pop() {
+        order.push(i);
+        for parent in &commits[i].parents {
+            if let Some(&parent) = index.get(parent.as_str()) {
+                children[parent] -= 1;
+                if children[parent] == 0 {
+                    ready.push(Reverse(parent));
+                }
+            }
+        }
+    }
+
+    let mut slots: Vec<Option<CommitMetadata>> = commits.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| slots[i].take()).collect()
+}
+
+/// Replace a file atomically, creating the directories leading to it
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".tmp.{}", std::process::id()));
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Recursively list the files below a directory
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
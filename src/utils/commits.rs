@@ -5,15 +5,21 @@
  */
 
 use std::fs;
-use std::process::Command;
+use serde::Serialize;
+use crate::error::{Error, Result};
+use crate::utils::backend::GitBackend;
+use crate::utils::hash::is_hash_like;
 
 /// Represents a commit entry with optional preceding comments
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct CommitEntry {
     /// Comments that precede this commit (including the commit they belong to)
     pub comments: Vec<String>,
     /// The commit information
     pub commit: CommitInfo,
+    /// Action keyword the line starts with, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<Action>,
 }
 
 impl CommitEntry {
@@ -21,20 +27,87 @@ impl CommitEntry {
         Self {
             comments,
             commit,
+            action: None,
         }
     }
 
+    /// What to do with the commit, picking it unless the line says otherwise
+    pub fn action(&self) -> Action {
+        self.action.unwrap_or(Action::Pick)
+    }
+
     /// Format the entry back to file lines
     pub fn to_lines(&self) -> Vec<String> {
         let mut lines = Vec::new();
         lines.extend(self.comments.clone());
-        lines.push(self.commit.to_line());
+        match self.action {
+            Some(action) => lines.push(format!("{} {}", action.keyword(), self.commit.to_line())),
+            None => lines.push(self.commit.to_line()),
+        }
         lines
     }
 }
 
+/// What `pick` does with a commit, written before its hash like in a `git rebase -i` todo list
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Pick the commit
+    Pick,
+    /// Fold the commit into the previous one, keeping both messages
+    Squash,
+    /// Fold the commit into the previous one, keeping only the previous message
+    Fixup,
+    /// Leave the commit out
+    Drop,
+    /// Pick the commit, then stop so it can be amended
+    Edit,
+}
+
+impl Action {
+    /// Parse an action keyword, accepting the one letter abbreviations git rebase does
+    pub fn from_keyword(word: &str) -> Option<Self> {
+        match word {
+            "pick" | "p" => Some(Action::Pick),
+            "squash" | "s" => Some(Action::Squash),
+            "fixup" | "f" => Some(Action::Fixup),
+            "drop" | "d" => Some(Action::Drop),
+            "edit" | "e" => Some(Action::Edit),
+            _ => None,
+        }
+    }
+
+    /// Keyword the action is written as
+    pub fn keyword(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Squash => "squash",
+            Action::Fixup => "fixup",
+            Action::Drop => "drop",
+            Action::Edit => "edit",
+        }
+    }
+
+    /// Whether the commit is folded into the previous one instead of becoming a commit of its own
+    pub fn folds(self) -> bool {
+        matches!(self, Action::Squash | Action::Fixup)
+    }
+
+    /// Split a leading action keyword off a line, if it has one
+    pub fn split_keyword(line: &str) -> (Option<Self>, &str) {
+        let line = line.trim();
+        match line.split_once(char::is_whitespace) {
+            Some((word, rest)) => match Self::from_keyword(word) {
+                Some(action) => (Some(action), rest.trim_start()),
+                None => (None, line),
+            },
+            None => (None, line),
+        }
+    }
+}
+
 /// Represents a commit with its hash, optional Change-Id, and optional title
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct CommitInfo {
     pub hash: String,
     pub change_id: Option<String>,
@@ -52,41 +125,31 @@ impl CommitInfo {
     }
 
     /// Parse a line from the commit file format: "hash [Change-Id] [title]"
-    pub fn parse_line(line: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    ///
+    /// The hash is the first word and has to look like a full or abbreviated
+    /// hash. A Change-Id is only recognized right after it, and everything
+    /// after that is the title, taken verbatim.
+    pub fn parse_line(line: &str) -> Result<Self> {
         let line = line.trim();
-        if line.is_empty() {
-            return Err("Empty line".into());
+        let (hash, mut rest) = split_word(line);
+        if !is_hash_like(hash) {
+            return Err(Error::ParseError {
+                line: line.to_string(),
+            });
         }
 
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.is_empty() {
-            return Err("No commit hash found".into());
-        }
-
-        let hash = parts[0].to_string();
         let mut change_id = None;
-        let mut title_parts = Vec::new();
 
-        // Parse remaining parts
-        for part in &parts[1..] {
-            if part.starts_with("I") && part.len() == 41 {
-                // Looks like a Gerrit Change-Id
-                change_id = Some(part.to_string());
-            } else {
-                title_parts.push(*part);
-            }
+        let (word, after) = split_word(rest);
+        if is_change_id(word) {
+            change_id = Some(word.to_string());
+            rest = after;
         }
 
-        let title = if title_parts.is_empty() {
-            None
-        } else {
-            Some(title_parts.join(" "))
-        };
-
         Ok(Self {
-            hash,
+            hash: hash.to_string(),
             change_id,
-            title,
+            title: (!rest.is_empty()).then(|| rest.to_string()),
         })
     }
 
@@ -106,42 +169,29 @@ impl CommitInfo {
     }
 
     /// Get commit message title from git if not already set, and expand hash to full if needed
-    pub fn fetch_title_if_missing(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn fetch_title_if_missing(&mut self, repo: &impl GitBackend) -> Result<()> {
         // First, get the full commit hash if we have a short one
-        self.expand_hash_to_full()?;
+        self.expand_hash_to_full(repo)?;
 
         if self.title.is_some() {
             return Ok(());
         }
 
-        let output = Command::new("git")
-            .args(["log", "--format=%s", "-n", "1", &self.hash])
-            .output()?;
-
-        if output.status.success() {
-            let title = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !title.is_empty() {
-                self.title = Some(title);
-            }
-        }
+        self.title = repo.title(&self.hash)?;
 
         Ok(())
     }
 
     /// Expand short commit hash to full hash
-    pub fn expand_hash_to_full(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        // If hash is already 40 characters (full SHA-1), no need to expand
-        if self.hash.len() == 40 {
+    pub fn expand_hash_to_full(&mut self, repo: &impl GitBackend) -> Result<()> {
+        // If hash is already full length for the repository's object format, no need to expand
+        let object_format = repo.object_format();
+        if object_format.is_full_hash(&self.hash) {
             return Ok(());
         }
 
-        let output = Command::new("git")
-            .args(["rev-parse", &self.hash])
-            .output()?;
-
-        if output.status.success() {
-            let full_hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if full_hash.len() == 40 {
+        if let Some(full_hash) = repo.resolve(&self.hash)? {
+            if object_format.is_full_hash(&full_hash) {
                 self.hash = full_hash;
             }
         }
@@ -150,37 +200,38 @@ impl CommitInfo {
     }
 
     /// Extract Change-Id from commit message if not already set
-    pub fn fetch_change_id_if_missing(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn fetch_change_id_if_missing(&mut self, repo: &impl GitBackend) -> Result<()> {
         if self.change_id.is_some() {
             return Ok(());
         }
 
-        let output = Command::new("git")
-            .args(["log", "--format=%B", "-n", "1", &self.hash])
-            .output()?;
-
-        if output.status.success() {
-            let body = String::from_utf8_lossy(&output.stdout);
-            for line in body.lines() {
-                if line.starts_with("Change-Id: I") {
-                    if let Some(change_id) = line.strip_prefix("Change-Id: ") {
-                        self.change_id = Some(change_id.trim().to_string());
-                        break;
-                    }
-                }
-            }
-        }
+        self.change_id = repo.metadata(&self.hash)?
+            .and_then(|metadata| metadata.change_id);
 
         Ok(())
     }
 }
 
+/// Check whether a word is a Gerrit Change-Id: "I" followed by 40 hex digits
+pub fn is_change_id(word: &str) -> bool {
+    word.strip_prefix('I')
+        .is_some_and(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Split the first word off a string, returning it and the rest with leading whitespace removed
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
 /// Utility for handling commit lists from files or command line arguments
 pub struct CommitsParser;
 
 impl CommitsParser {
     /// Read commit entries from a file, preserving comments
-    pub fn read_from_file(file_path: &str) -> Result<(Vec<String>, Vec<CommitEntry>), Box<dyn std::error::Error>> {
+    pub fn read_from_file(file_path: &str) -> Result<(Vec<String>, Vec<CommitEntry>)> {
         let content = fs::read_to_string(file_path)?;
         let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
 
@@ -213,14 +264,18 @@ impl CommitsParser {
                 // Comment line
                 current_comments.push(lines[line_idx].clone());
             } else {
-                // This should be a commit line
-                match CommitInfo::parse_line(line) {
+                // This should be a commit line, optionally starting with an action
+                let (action, commit_line) = Action::split_keyword(line);
+                match CommitInfo::parse_line(commit_line) {
                     Ok(commit) => {
-                        entries.push(CommitEntry::with_comments(commit, current_comments.clone()));
+                        let mut entry = CommitEntry::with_comments(commit, current_comments.clone());
+                        entry.action = action;
+                        entries.push(entry);
                         current_comments.clear();
                     }
                     Err(_) => {
-                        // If it's not a valid commit, treat it as a comment
+                        // Kept as a comment, but a mistyped hash must not drop a commit unnoticed
+                        eprintln!("WARNING: {}:{}: not a commit, treating it as a comment: {}", file_path, line_idx + 1, line);
                         current_comments.push(lines[line_idx].clone());
                     }
                 }
@@ -229,14 +284,19 @@ impl CommitsParser {
         }
 
         if entries.is_empty() {
-            return Err("No commits found in file".into());
+            return Err(Error::NoCommits(file_path.to_string()));
         }
 
         Ok((modelines, entries))
     }
 
     /// Write commit entries to a file, preserving comments and adding vim modeline
-    pub fn write_to_file(file_path: &str, modelines: &[String], entries: &[CommitEntry]) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn write_to_file(
+        repo: &impl GitBackend,
+        file_path: &str,
+        modelines: &[String],
+        entries: &[CommitEntry]
+    ) -> Result<()> {
         let mut all_lines = Vec::new();
 
         // Add vim modeline if not already present
@@ -249,8 +309,8 @@ impl CommitsParser {
         // Add entries with enriched information
         for entry in entries {
             let mut enriched_entry = entry.clone();
-            enriched_entry.commit.fetch_change_id_if_missing()?;
-            enriched_entry.commit.fetch_title_if_missing()?;
+            enriched_entry.commit.fetch_change_id_if_missing(repo)?;
+            enriched_entry.commit.fetch_title_if_missing(repo)?;
 
             let entry_lines = enriched_entry.to_lines();
             all_lines.extend(entry_lines);
@@ -265,17 +325,26 @@ impl CommitsParser {
     pub fn get_commits(
         cli_commits: Vec<String>,
         commits_file: Option<String>
-    ) -> Result<(Vec<CommitInfo>, Option<String>), Box<dyn std::error::Error>> {
+    ) -> Result<(Vec<CommitInfo>, Option<String>)> {
+        let (entries, file_path) = Self::get_entries(cli_commits, commits_file)?;
+        let commits = entries.into_iter().map(|e| e.commit).collect();
+        Ok((commits, file_path))
+    }
+
+    /// Get commit entries from either command line arguments or file, keeping the file's comments
+    pub fn get_entries(
+        cli_commits: Vec<String>,
+        commits_file: Option<String>
+    ) -> Result<(Vec<CommitEntry>, Option<String>)> {
         if let Some(file_path) = commits_file {
             let (_, entries) = Self::read_from_file(&file_path)?;
-            let commits = entries.into_iter().map(|e| e.commit).collect();
-            Ok((commits, Some(file_path)))
+            Ok((entries, Some(file_path)))
         } else {
-            // Convert simple strings to CommitInfo
-            let commits = cli_commits.into_iter()
-                .map(CommitInfo::from_hash)
+            // Convert simple strings to entries without comments
+            let entries = cli_commits.into_iter()
+                .map(|hash| CommitEntry::with_comments(CommitInfo::from_hash(hash), Vec::new()))
                 .collect();
-            Ok((commits, None))
+            Ok((entries, None))
         }
     }
 
@@ -284,3 +353,57 @@ impl CommitsParser {
         commits.iter().map(|c| c.hash.clone()).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const CHANGE_ID: &str = "I0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn change_id_only_after_hash() {
+        let title = format!("Revert \"{}\"  and   more", CHANGE_ID);
+        let commit = CommitInfo::parse_line(&format!("abcdef1 {}", title)).unwrap();
+        assert_eq!(commit.change_id, None);
+        assert_eq!(commit.title.as_deref(), Some(title.as_str()));
+
+        let commit = CommitInfo::parse_line(&format!("abcdef1 {} {}", CHANGE_ID, title)).unwrap();
+        assert_eq!(commit.change_id.as_deref(), Some(CHANGE_ID));
+        assert_eq!(commit.title.as_deref(), Some(title.as_str()));
+    }
+
+    #[test]
+    fn rejects_malformed_change_id() {
+        let not_hex = format!("I{}", "g".repeat(40));
+        let commit = CommitInfo::parse_line(&format!("abcdef1 {} title", not_hex)).unwrap();
+        assert_eq!(commit.change_id, None);
+        assert_eq!(commit.title, Some(format!("{} title", not_hex)));
+    }
+
+    #[test]
+    fn rejects_lines_not_starting_with_hash() {
+        for line in ["", "fixup! abcdef1 title", "abcdef title", "abcdefg title", &"a".repeat(65)] {
+            assert!(CommitInfo::parse_line(line).is_err(), "{:?} was parsed", line);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn line_round_trip(
+            hash in "[0-9a-f]{7,40}",
+            change_id in proptest::option::of("I[0-9a-f]{40}"),
+            title in proptest::option::of("[^\\s]([^\\n\\r]*[^\\s])?"),
+        ) {
+            // Without a Change-Id, a title starting with one reads as the Change-Id
+            prop_assume!(change_id.is_some() || !title.as_deref().is_some_and(|title| is_change_id(split_word(title).0)));
+
+            let commit = CommitInfo {
+                hash,
+                change_id,
+                title,
+            };
+            prop_assert_eq!(CommitInfo::parse_line(&commit.to_line()).unwrap(), commit);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use crate::error::Result;
+use crate::utils::backend::GitBackend;
+use crate::utils::hash::ObjectFormat;
+use crate::utils::metadata::CommitMetadata;
+use crate::utils::repo::History;
+
+/// An in-memory repository for unit tests
+///
+/// Commits are made with [`Self::commit`] and [`Self::commit_change`], each
+/// one counting as newer than the ones made before it, and refs like `HEAD`
+/// are pointed at them with [`Self::set_ref`].
+#[derive(Default)]
+pub struct FakeRepo {
+    /// Commits in the order they were made
+    commits: Vec<FakeCommit>,
+    refs: HashMap<String, String>,
+}
+
+struct FakeCommit {
+    metadata: CommitMetadata,
+    /// Files the commit changes, sorted
+    files: Vec<String>,
+    patch_id: Option<String>,
+}
+
+impl FakeRepo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make an empty commit with the given parents and message, returning its hash
+    pub fn commit(&mut self, parents: &[&str], message: &str) -> String {
+        self.add(parents, message, Vec::new(), None)
+    }
+
+    /// Make a commit changing `files` with a diff identified by `patch_id`, returning its hash
+    pub fn commit_change(&mut self, parents: &[&str], message: &str, files: &[&str], patch_id: &str) -> String {
+        let mut files: Vec<String> = files.iter().map(|file| file.to_string()).collect();
+        files.sort();
+        self.add(parents, message, files, Some(patch_id.to_string()))
+    }
+
+    /// Point a ref, such as a branch name or `HEAD`, at a commit
+    pub fn set_ref(&mut self, name: &str, hash: &str) {
+        self.refs.insert(name.to_string(), hash.to_string());
+    }
+
+    fn add(&mut self, parents: &[&str], message: &str, files: Vec<String>, patch_id: Option<String>) -> String {
+        let hash = fake_hash(self.commits.len(), message);
+        let parents = parents.iter().map(|parent| parent.to_string()).collect();
+        self.commits.push(FakeCommit {
+            metadata: CommitMetadata::parse(hash.clone(), parents, message),
+            files,
+            patch_id,
+        });
+        hash
+    }
+
+    fn find(&self, rev: &str) -> Option<&FakeCommit> {
+        let hash = self.resolve(rev).ok().flatten()?;
+        self.commits.iter().find(|commit| commit.metadata.hash == hash)
+    }
+}
+
+/// A made up SHA-1 hash, different for every commit
+fn fake_hash(counter: usize, message: &str) -> String {
+    (0..3u8)
+        .map(|salt| {
+            let mut hasher = DefaultHasher::new();
+            (salt, counter, message).hash(&mut hasher);
+            format!("{:016x}", hasher.finish())
+        })
+        .collect::<String>()[..40]
+        .to_string()
+}
+
+impl GitBackend for FakeRepo {
+    fn object_format(&self) -> ObjectFormat {
+        ObjectFormat::Sha1
+    }
+
+    fn resolve(&self, rev: &str) -> Result<Option<String>> {
+        if let Some(hash) = self.refs.get(rev) {
+            return Ok(Some(hash.clone()));
+        }
+
+        let rev = rev.to_ascii_lowercase();
+        let mut matching = self.commits.iter()
+            .map(|commit| &commit.metadata.hash)
+            .filter(|hash| hash.starts_with(&rev));
+        match (matching.next(), matching.next()) {
+            (Some(hash), None) => Ok(Some(hash.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    fn metadata(&self, rev: &str) -> Result<Option<CommitMetadata>> {
+        Ok(self.find(rev).map(|commit| commit.metadata.clone()))
+    }
+
+    fn history(&self, tip: &str) -> Result<Rc<History>> {
+        let mut reachable = HashSet::new();
+        let mut stack: Vec<String> = self.resolve(tip)?.into_iter().collect();
+        while let Some(hash) = stack.pop() {
+            if reachable.insert(hash.clone()) {
+                if let Some(commit) = self.find(&hash) {
+                    stack.extend(commit.metadata.parents.iter().cloned());
+                }
+            }
+        }
+
+        // Newest first, like a walk by commit time
+        let commits = self.commits.iter()
+            .rev()
+            .filter(|commit| reachable.contains(&commit.metadata.hash))
+            .map(|commit| commit.metadata.clone())
+            .collect();
+        Ok(Rc::new(History::new(commits)))
+    }
+
+    fn patch_id(&self, rev: &str) -> Result<Option<String>> {
+        Ok(self.find(rev).and_then(|commit| commit.patch_id.clone()))
+    }
+
+    fn changed_files(&self, rev: &str) -> Result<Vec<String>> {
+        Ok(self.find(rev).map(|commit| commit.files.clone()).unwrap_or_default())
+    }
+
+    fn commit_time(&self, rev: &str) -> Result<Option<i64>> {
+        let hash = self.resolve(rev)?;
+        Ok(self.commits.iter()
+            .position(|commit| Some(&commit.metadata.hash) == hash.as_ref())
+            .map(|made| made as i64))
+    }
+}
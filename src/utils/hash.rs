@@ -0,0 +1,123 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// Shortest hex word that is considered to be an abbreviated commit hash, like git's minimum abbreviation
+pub const MIN_ABBREV_LEN: usize = 7;
+
+/// Length of the longest full hash of any object format
+const MAX_HEX_LEN: usize = 64;
+
+/// Hash function a repository names its objects with, see `extensions.objectFormat`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// Parse the name git uses for an object format, as printed by `git rev-parse --show-object-format`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "sha1" => Some(Self::Sha1),
+            "sha256" => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    /// Number of hex digits in a full hash
+    pub fn hex_len(self) -> usize {
+        match self {
+            Self::Sha1 => 40,
+            Self::Sha256 => 64,
+        }
+    }
+
+    /// Number of hex digits shown when abbreviating hashes for humans, e.g. in fix chain comments
+    pub fn display_abbrev_len(self) -> usize {
+        match self {
+            Self::Sha1 => 12,
+            Self::Sha256 => 16,
+        }
+    }
+
+    /// Check whether a word is a full hash in this format
+    pub fn is_full_hash(self, word: &str) -> bool {
+        word.len() == self.hex_len() && word.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    /// Check whether a word can be a full or abbreviated hash in this format
+    pub fn is_hash_like(self, word: &str) -> bool {
+        is_hash_like(word) && word.len() <= self.hex_len()
+    }
+
+    /// Check whether two hashes, either of which may be abbreviated, name the same object
+    pub fn hashes_match(self, a: &str, b: &str) -> bool {
+        if !self.is_hash_like(a) || !self.is_hash_like(b) {
+            return false;
+        }
+        let (a, b) = (a.to_ascii_lowercase(), b.to_ascii_lowercase());
+        a.starts_with(&b) || b.starts_with(&a)
+    }
+
+    /// Abbreviate a hash for display
+    pub fn abbreviate(self, hash: &str) -> &str {
+        &hash[..std::cmp::min(self.display_abbrev_len(), hash.len())]
+    }
+}
+
+/// Check whether a word looks like an abbreviated or full hash of any object format
+pub fn is_hash_like(word: &str) -> bool {
+    (MIN_ABBREV_LEN..=MAX_HEX_LEN).contains(&word.len()) && word.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "3bbfc6c90544f560965236699f251722dd96e765";
+    const SHA256: &str = "f62a8f8a18f77820a3864f35889d942c11502a2ac7828796c3f1e3f3007a3d4d";
+
+    #[test]
+    fn parses_format_names() {
+        assert_eq!(ObjectFormat::from_name("sha1\n"), Some(ObjectFormat::Sha1));
+        assert_eq!(ObjectFormat::from_name("sha256"), Some(ObjectFormat::Sha256));
+        assert_eq!(ObjectFormat::from_name("md5"), None);
+    }
+
+    #[test]
+    fn full_hash_length_follows_format() {
+        assert!(ObjectFormat::Sha1.is_full_hash(SHA1));
+        assert!(!ObjectFormat::Sha1.is_full_hash(SHA256));
+        assert!(ObjectFormat::Sha256.is_full_hash(SHA256));
+        assert!(!ObjectFormat::Sha256.is_full_hash(SHA1));
+    }
+
+    #[test]
+    fn abbreviation_follows_format() {
+        assert_eq!(ObjectFormat::Sha1.abbreviate(SHA1), "3bbfc6c90544");
+        assert_eq!(ObjectFormat::Sha256.abbreviate(SHA256), "f62a8f8a18f77820");
+        assert_eq!(ObjectFormat::Sha256.abbreviate("f62a8f8"), "f62a8f8");
+    }
+
+    #[test]
+    fn matches_abbreviated_hashes() {
+        assert!(ObjectFormat::Sha256.hashes_match(SHA256, "F62A8F8A18F7"));
+        assert!(ObjectFormat::Sha256.hashes_match("f62a8f8a18f7", SHA256));
+        assert!(!ObjectFormat::Sha256.hashes_match(SHA256, "f62a8f9"));
+        // Too short to be an abbreviation
+        assert!(!ObjectFormat::Sha256.hashes_match(SHA256, "f62a8f"));
+    }
+
+    #[test]
+    fn sha256_words_are_not_sha1_hashes() {
+        // A 40 digit prefix of a SHA-256 hash is a valid abbreviation in a SHA-256 repository...
+        assert!(ObjectFormat::Sha256.hashes_match(SHA256, &SHA256[..40]));
+        // ...but a full SHA-256 hash can't name an object in a SHA-1 repository
+        assert!(!ObjectFormat::Sha1.hashes_match(&SHA256[..40], SHA256));
+        assert!(!ObjectFormat::Sha1.is_hash_like(SHA256));
+    }
+}
@@ -0,0 +1,367 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use crate::utils::hash::{ObjectFormat, MIN_ABBREV_LEN};
+use crate::utils::metadata::{titles_match, CommitMetadata};
+use crate::utils::repo::History;
+
+/// Shortest hash prefix that is indexed, matching what `git log --grep` used to search for
+const INDEX_PREFIX_LEN: usize = MIN_ABBREV_LEN;
+
+/// Trailers and hash mentions of every commit on a branch, built in a single pass
+///
+/// This answers the questions the fix command asks about the reference branch
+/// (which commit has this Change-Id, which commits fix or mention that commit)
+/// without scanning the whole branch again for every backported commit.
+///
+/// Abbreviated hashes are matched like git resolves them: a written hash only
+/// refers to a commit when no other commit it could have meant shares the
+/// prefix, so commits that merely share a prefix with the looked up commit
+/// are not returned.
+pub struct TrailerIndex {
+    history: Rc<History>,
+    object_format: ObjectFormat,
+    /// Hash prefix → positions of the commits whose hash starts with it
+    commits: HashMap<String, Vec<usize>>,
+    /// Change-Id trailer value → positions of commits carrying it, newest first
+    change_ids: HashMap<String, Vec<usize>>,
+    /// Hash prefix → hashes written in `Fixes:` trailers
+    fixes: HashMap<String, Vec<Reference>>,
+    /// Hash prefix → hashes written in "This reverts commit" lines
+    reverts: HashMap<String, Vec<Reference>>,
+    /// Hash prefix → hash-like words anywhere in a message
+    mentions: HashMap<String, Vec<Reference>>,
+}
+
+/// A hash written in the message of a commit
+struct Reference {
+    /// The hash as written, lowercased
+    written: String,
+    /// Position of the commit whose message contains the hash
+    position: usize,
+    /// Title quoted next to the hash, if any
+    title: Option<String>,
+}
+
+/// A commit with a `Fixes:` trailer naming the looked up commit
+pub struct FixReference {
+    pub hash: String,
+    /// Title quoted in the trailer, when it is not the title of the fixed commit
+    pub mismatched_title: Option<String>,
+}
+
+impl TrailerIndex {
+    /// Index every commit of a history
+    pub fn build(history: Rc<History>, object_format: ObjectFormat) -> Self {
+        let trailers = SegmentTrailers::build(history.commits(), object_format);
+        Self::from_segments(history, object_format, vec![trailers])
+    }
+
+    /// Assemble the index of a history from the trailers of the segments it is stored in
+    ///
+    /// Segments may describe commits the history does not reach, those are left out.
+    pub fn from_segments(history: Rc<History>, object_format: ObjectFormat, segments: Vec<SegmentTrailers>) -> Self {
+        let mut index = Self {
+            history: history.clone(),
+            object_format,
+            commits: HashMap::new(),
+            change_ids: HashMap::new(),
+            fixes: HashMap::new(),
+            reverts: HashMap::new(),
+            mentions: HashMap::new(),
+        };
+
+        for (position, commit) in history.commits().iter().enumerate() {
+            index.commits.entry(commit.hash[..INDEX_PREFIX_LEN].to_ascii_lowercase())
+                .or_default()
+                .push(position);
+        }
+
+        for segment in segments {
+            for (change_id, hashes) in segment.change_ids {
+                let positions = index.change_ids.entry(change_id).or_default();
+                positions.extend(hashes.iter().filter_map(|hash| history.position(hash)));
+            }
+            for (map, stored) in [
+                (&mut index.fixes, segment.fixes),
+                (&mut index.reverts, segment.reverts),
+                (&mut index.mentions, segment.mentions),
+            ] {
+                for (prefix, references) in stored {
+                    let resolved = references.into_iter().filter_map(|reference| Some(Reference {
+                        position: history.position(&reference.commit)?,
+                        written: reference.written,
+                        title: reference.title,
+                    }));
+                    map.entry(prefix).or_default().extend(resolved);
+                }
+            }
+        }
+
+        // Keep Change-Id lookups newest first however the segments were ordered
+        for positions in index.change_ids.values_mut() {
+            positions.sort_unstable();
+            positions.dedup();
+        }
+
+        index
+    }
+
+    /// Find the newest commit carrying the given Change-Id
+    pub fn find_by_change_id(&self, change_id: &str) -> Option<String> {
+        self.change_ids.get(change_id)
+            .and_then(|positions| positions.first())
+            .map(|&position| self.history.hash_at(position).to_string())
+    }
+
+    /// Find commits with a `Fixes:` trailer pointing at the given commit
+    pub fn fixes_of(&self, hash: &str) -> Vec<FixReference> {
+        let title = self.history.position(&hash.to_ascii_lowercase())
+            .and_then(|target| self.history.commits()[target].title.as_deref());
+
+        let mut result: Vec<FixReference> = Vec::new();
+        for reference in self.lookup(&self.fixes, hash) {
+            let mismatched_title = reference.title.as_deref()
+                .filter(|quoted| title.is_some_and(|title| !titles_match(quoted, title)))
+                .map(str::to_string);
+
+            let fix_hash = self.history.hash_at(reference.position);
+            match result.iter_mut().find(|fix| fix.hash == fix_hash) {
+                // The same commit fixing it twice only needs to quote the title right once
+                Some(fix) => {
+                    if mismatched_title.is_none() {
+                        fix.mismatched_title = None;
+                    }
+                }
+                None => result.push(FixReference {
+                    hash: fix_hash.to_string(),
+                    mismatched_title,
+                }),
+            }
+        }
+        result
+    }
+
+    /// Find commits reverting the given commit
+    pub fn reverts_of(&self, hash: &str) -> Vec<String> {
+        self.referring_commits(&self.reverts, hash)
+    }
+
+    /// Find commits mentioning the given commit anywhere in their message
+    pub fn mentions_of(&self, hash: &str) -> Vec<String> {
+        self.referring_commits(&self.mentions, hash)
+    }
+
+    /// Hashes of the commits returned by [`Self::lookup`], each listed once
+    fn referring_commits(&self, map: &HashMap<String, Vec<Reference>>, hash: &str) -> Vec<String> {
+        let mut positions: Vec<usize> = self.lookup(map, hash)
+            .map(|reference| reference.position)
+            .collect();
+        positions.dedup();
+
+        positions.into_iter()
+            .map(|position| self.history.hash_at(position).to_string())
+            .collect()
+    }
+
+    /// Look up hashes written in messages that refer to `hash`, ordered by referring commit
+    ///
+    /// A referring commit can only be an ancestor of `hash` when two different
+    /// commits share an abbreviated hash, so those are dropped just like
+    /// `git log <hash>..<branch>` would.
+    fn lookup<'a>(&'a self, map: &'a HashMap<String, Vec<Reference>>, hash: &str) -> impl Iterator<Item = &'a Reference> {
+        let hash = hash.to_ascii_lowercase();
+        let candidates = self.object_format.is_hash_like(&hash)
+            .then(|| map.get(&hash[..INDEX_PREFIX_LEN]))
+            .flatten()
+            .map_or(&[][..], Vec::as_slice);
+        let target = self.history.position(&hash);
+
+        let mut result: Vec<&Reference> = candidates.iter()
+            .filter(|reference| self.object_format.hashes_match(&reference.written, &hash))
+            .filter(|reference| match target {
+                Some(target) => !self.history.is_ancestor_at(reference.position, target)
+                    && self.refers_to(reference, target),
+                None => true,
+            })
+            .collect();
+        result.sort_by_key(|reference| reference.position);
+        result.into_iter()
+    }
+
+    /// Check whether a written hash names the commit at `target`
+    ///
+    /// The hash only names `target` when it is the one commit the author
+    /// could have meant: among all commits sharing the prefix, only those
+    /// that existed when the referring commit was written (its ancestors) are
+    /// considered, and a quoted title settles the remaining ambiguity.
+    fn refers_to(&self, reference: &Reference, target: usize) -> bool {
+        let written = &reference.written;
+        let mut candidates: Vec<usize> = self.commits.get(&written[..INDEX_PREFIX_LEN])
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .copied()
+            .filter(|&position| self.history.hash_at(position).starts_with(written.as_str()))
+            .collect();
+
+        if candidates.len() > 1 {
+            candidates.retain(|&position| position != reference.position
+                && self.history.is_ancestor_at(position, reference.position));
+        }
+        if candidates.len() > 1 {
+            if let Some(quoted) = &reference.title {
+                candidates.retain(|&position| self.history.commits()[position].title.as_deref()
+                    .is_some_and(|title| titles_match(quoted, title)));
+            }
+        }
+
+        if candidates != [target] {
+            debug!("{} in {} does not unambiguously name {}", written,
+                   self.history.hash_at(reference.position), self.history.hash_at(target));
+            return false;
+        }
+        true
+    }
+}
+
+/// Trailers and hash mentions of some commits, keyed by commit hash rather than position
+///
+/// This is what a cache segment stores next to its commits, so the index of
+/// a cached branch is assembled from the segments without going through the
+/// messages again.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SegmentTrailers {
+    /// Change-Id trailer value → hashes of the commits carrying it
+    change_ids: HashMap<String, Vec<String>>,
+    /// Hash prefix → hashes written in `Fixes:` trailers
+    fixes: HashMap<String, Vec<StoredReference>>,
+    /// Hash prefix → hashes written in "This reverts commit" lines
+    reverts: HashMap<String, Vec<StoredReference>>,
+    /// Hash prefix → hash-like words anywhere in a message
+    mentions: HashMap<String, Vec<StoredReference>>,
+}
+
+/// A hash written in the message of a commit, naming that commit by hash
+#[derive(Serialize, Deserialize)]
+struct StoredReference {
+    written: String,
+    commit: String,
+    title: Option<String>,
+}
+
+impl SegmentTrailers {
+    /// Collect the trailers of the given commits
+    pub fn build(commits: &[CommitMetadata], object_format: ObjectFormat) -> Self {
+        let mut trailers = Self::default();
+        for commit in commits {
+            if let Some(change_id) = &commit.change_id {
+                trailers.change_ids.entry(change_id.clone()).or_default().push(commit.hash.clone());
+            }
+            for fixes in &commit.fixes {
+                insert_hash(&mut trailers.fixes, object_format, &fixes.hash, &commit.hash, fixes.title.clone());
+            }
+            for hash in &commit.reverts {
+                insert_hash(&mut trailers.reverts, object_format, hash, &commit.hash, None);
+            }
+            for hash in &commit.mentions {
+                insert_hash(&mut trailers.mentions, object_format, hash, &commit.hash, None);
+            }
+        }
+        trailers
+    }
+}
+
+/// Record a hash-like word under its prefix, ignoring words that can't be hashes in the repository's format
+fn insert_hash(map: &mut HashMap<String, Vec<StoredReference>>, object_format: ObjectFormat, word: &str, commit: &str, title: Option<String>) {
+    if !object_format.is_hash_like(word) {
+        return;
+    }
+
+    let word = word.to_ascii_lowercase();
+    map.entry(word[..INDEX_PREFIX_LEN].to_string())
+        .or_default()
+        .push(StoredReference {
+            written: word,
+            commit: commit.to_string(),
+            title,
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fake 40 digit hash starting with `prefix`
+    fn hash(prefix: &str) -> String {
+        format!("{:0<40}", prefix)
+    }
+
+    fn commit(hash: &str, parents: &[&str], message: &str) -> CommitMetadata {
+        CommitMetadata::parse(hash.to_string(), parents.iter().map(|p| p.to_string()).collect(), message)
+    }
+
+    /// Two commits sharing the prefix `abcdef1` on separate branches, merged at the end:
+    ///
+    /// ```text
+    /// root - old ("old feature") - fix (Fixes: abcdef1 ...) - merge
+    ///     \                                                   /
+    ///      new ("new feature") ---------------------------------
+    /// ```
+    fn colliding_history(fixes_line: &str) -> (TrailerIndex, String, String, String) {
+        let (root, old, new, fix, merge) = (hash("1"), hash("abcdef11"), hash("abcdef12"), hash("2"), hash("3"));
+        let commits = vec![
+            commit(&merge, &[&fix, &new], "Merge"),
+            commit(&fix, &[&old], &format!("fix it\n\n{}\nSee also abcdef1 for details.", fixes_line)),
+            commit(&new, &[&root], "new feature"),
+            commit(&old, &[&root], "old feature"),
+            commit(&root, &[], "root"),
+        ];
+        let index = TrailerIndex::build(Rc::new(History::new(commits)), ObjectFormat::Sha1);
+        (index, old, new, fix)
+    }
+
+    #[test]
+    fn shared_prefix_is_not_a_fix() {
+        let (index, old, new, fix) = colliding_history("Fixes: abcdef1 (\"old feature\")");
+
+        let fixes: Vec<String> = index.fixes_of(&old).into_iter().map(|fix| fix.hash).collect();
+        assert_eq!(fixes, vec![fix.clone()]);
+        assert!(index.fixes_of(&new).is_empty());
+        assert_eq!(index.mentions_of(&old), vec![fix]);
+        assert!(index.mentions_of(&new).is_empty());
+    }
+
+    #[test]
+    fn quoted_title_settles_ambiguous_prefix() {
+        let (root, old, new, fix) = (hash("1"), hash("abcdef11"), hash("abcdef12"), hash("2"));
+        let commits = vec![
+            commit(&fix, &[&new], "fix it\n\nFixes: abcdef1 (\"new\n feature\")"),
+            commit(&new, &[&old], "new feature"),
+            commit(&old, &[&root], "old feature"),
+            commit(&root, &[], "root"),
+        ];
+        let index = TrailerIndex::build(Rc::new(History::new(commits)), ObjectFormat::Sha1);
+
+        let fixes = index.fixes_of(&new);
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].hash, fix);
+        assert_eq!(fixes[0].mismatched_title, None);
+        assert!(index.fixes_of(&old).is_empty());
+    }
+
+    #[test]
+    fn reports_mismatched_title() {
+        let (index, old, _, _) = colliding_history("Fixes: abcdef11 (\"something else\")");
+
+        let fixes = index.fixes_of(&old);
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].mismatched_title.as_deref(), Some("something else"));
+    }
+}
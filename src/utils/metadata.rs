@@ -0,0 +1,133 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use serde::{Deserialize, Serialize};
+use crate::utils::hash::is_hash_like;
+
+/// Everything the commands need to know about a commit, extracted from its message once
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CommitMetadata {
+    pub hash: String,
+    pub parents: Vec<String>,
+    pub title: Option<String>,
+    pub change_id: Option<String>,
+    pub was_change_ids: Vec<String>,
+    /// `Fixes:` trailers, with the hash as written
+    pub fixes: Vec<FixesTrailer>,
+    /// Hashes written in "This reverts commit <hash>." lines
+    pub reverts: Vec<String>,
+    /// Hashes written in "(cherry picked from commit <hash>)" lines
+    pub cherry_picked_from: Vec<String>,
+    /// Every hash-like word in the message, lowercased
+    pub mentions: Vec<String>,
+}
+
+/// A `Fixes: <hash> ("<title>")` trailer
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FixesTrailer {
+    pub hash: String,
+    /// Title of the fixed commit as quoted in the trailer
+    pub title: Option<String>,
+}
+
+impl CommitMetadata {
+    /// Extract metadata from a raw commit message
+    pub fn parse(hash: String, parents: Vec<String>, message: &str) -> Self {
+        let title = subject(message);
+        let mut metadata = Self {
+            hash,
+            parents,
+            title: (!title.is_empty()).then_some(title),
+            ..Default::default()
+        };
+
+        let lines: Vec<&str> = message.lines().collect();
+        for (i, line) in lines.iter().enumerate() {
+            let line = line.trim();
+
+            if let Some(change_id) = line.strip_prefix("Change-Id:") {
+                let change_id = change_id.trim();
+                if metadata.change_id.is_none() && !change_id.is_empty() {
+                    metadata.change_id = Some(change_id.to_string());
+                }
+            } else if let Some(was_change_id) = line.strip_prefix("Was-Change-Id:") {
+                let was_change_id = was_change_id.trim();
+                if !was_change_id.is_empty() {
+                    metadata.was_change_ids.push(was_change_id.to_string());
+                }
+            } else if let Some(fixes_part) = line.strip_prefix("Fixes:") {
+                // Extract the commit hash part (before any space or parenthesis)
+                let fixes_part = fixes_part.trim_start();
+                if let Some(hash) = fixes_part.split_whitespace().next() {
+                    metadata.fixes.push(FixesTrailer {
+                        hash: hash.to_string(),
+                        title: quoted_title(&fixes_part[hash.len()..], &lines[i + 1..]),
+                    });
+                }
+            } else if let Some(rest) = line.strip_prefix("This reverts commit ") {
+                let hash = rest.trim_end_matches(|c: char| c == '.' || c == ',' || c.is_whitespace());
+                if is_hash_like(hash) {
+                    metadata.reverts.push(hash.to_string());
+                }
+            } else if let Some(rest) = line.strip_prefix("(cherry picked from commit ") {
+                let hash = rest.trim_end_matches(')').trim();
+                if is_hash_like(hash) {
+                    metadata.cherry_picked_from.push(hash.to_string());
+                }
+            }
+
+            for word in line.split(|c: char| !c.is_ascii_alphanumeric()) {
+                if is_hash_like(word) {
+                    let word = word.to_ascii_lowercase();
+                    if !metadata.mentions.contains(&word) {
+                        metadata.mentions.push(word);
+                    }
+                }
+            }
+        }
+
+        metadata
+    }
+}
+
+/// Extract the title quoted after the hash of a `Fixes:` trailer, e.g. `("title")`
+///
+/// Long titles are sometimes wrapped, so the quote is continued on the
+/// following lines until it is closed.
+fn quoted_title(rest: &str, following: &[&str]) -> Option<String> {
+    let rest = rest.trim().strip_prefix('(')?;
+    let mut title = rest.strip_prefix('"').unwrap_or(rest).to_string();
+
+    let mut following = following.iter();
+    while !title.ends_with(')') && !title.ends_with('"') {
+        match following.next() {
+            Some(line) if !line.trim().is_empty() => {
+                title.push(' ');
+                title.push_str(line.trim());
+            }
+            _ => break,
+        }
+    }
+
+    let title = title.strip_suffix(')').unwrap_or(&title);
+    let title = title.strip_suffix('"').unwrap_or(title).trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+/// Check whether a title quoted in a trailer is the title of a commit, ignoring differences in whitespace
+pub fn titles_match(quoted: &str, title: &str) -> bool {
+    quoted.split_whitespace().eq(title.split_whitespace())
+}
+
+/// Extract the subject of a commit message: its first paragraph joined into one line
+pub fn subject(message: &str) -> String {
+    message.trim_start()
+        .lines()
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -5,3 +5,12 @@
  */
 
 pub mod commits;
+pub mod output;
+pub mod repo;
+pub mod index;
+pub mod metadata;
+pub mod cache;
+pub mod hash;
+pub mod backend;
+#[cfg(test)]
+pub mod fake;
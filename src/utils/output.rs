@@ -0,0 +1,24 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use serde::Serialize;
+use crate::error::Result;
+
+/// Output format shared by all commands
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable text, commits are written in the commits file format
+    #[default]
+    Text,
+    /// A structured JSON document for consumption by other tools
+    Json,
+}
+
+/// Print a value as pretty JSON to stdout
+pub fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
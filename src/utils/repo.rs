@@ -0,0 +1,1316 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use log::debug;
+use crate::error::{Error, Result};
+use crate::utils::backend::GitBackend;
+use crate::utils::cache::Cache;
+use crate::utils::hash::ObjectFormat;
+use crate::utils::index::{SegmentTrailers, TrailerIndex};
+use crate::utils::metadata::CommitMetadata;
+
+/// How many of the most recently cached tips a new branch is compared to when picking the bases of its segment
+const MAX_BASE_CANDIDATES: usize = 8;
+
+/// Access to the git repository of the current directory
+///
+/// Queries are answered in-process through gix. When the repository cannot be
+/// opened that way, or `GIT_BP_BACKEND=subprocess` is set, every query falls
+/// back to running `git` instead. Histories are walked once per tip and kept
+/// in memory, so repeated lookups on the same branch don't touch git again.
+/// Branch histories are additionally cached on disk, see [`Cache`].
+pub struct Repository {
+    gix: Option<gix::Repository>,
+    object_format: ObjectFormat,
+    histories: RefCell<HashMap<String, Rc<History>>>,
+    /// Commits in `exclude..tip`, keyed by the range with both ends resolved
+    ranges: RefCell<HashMap<String, Rc<Vec<String>>>>,
+    /// Metadata of the commits in `exclude..tip` walked on their own, keyed like `ranges`
+    range_metadata: RefCell<HashMap<String, Rc<Vec<CommitMetadata>>>>,
+    trailer_indexes: RefCell<HashMap<String, Rc<TrailerIndex>>>,
+    /// Patch-ids of the commits in `exclude..tip`, keyed like `ranges`
+    patch_ids: RefCell<HashMap<String, Rc<HashMap<String, String>>>>,
+    /// Patch-id of every commit computed so far, `None` for commits without a diff
+    commit_patch_ids: RefCell<HashMap<String, Option<String>>>,
+}
+
+/// Line ranges of a file, as `(start, count)` pairs with 1-based line numbers
+pub struct FileLines {
+    pub path: String,
+    pub ranges: Vec<(usize, usize)>,
+}
+
+/// All commits reachable from a tip, loaded in a single walk
+pub struct History {
+    /// Commits ordered newest first by commit time
+    commits: Vec<CommitMetadata>,
+    /// Position of each commit in `commits`, keyed by full hash
+    index: HashMap<String, usize>,
+    /// Positions of the parents of each commit, parents outside the history are left out
+    parents: Vec<Vec<usize>>,
+    /// Topological rank of each commit: a commit always ranks lower than its parents
+    rank: Vec<usize>,
+}
+
+impl History {
+    /// Build a history from commits ordered newest first
+    pub fn new(commits: Vec<CommitMetadata>) -> Self {
+        let index: HashMap<String, usize> = commits.iter()
+            .enumerate()
+            .map(|(i, commit)| (commit.hash.clone(), i))
+            .collect();
+        let parents: Vec<Vec<usize>> = commits.iter()
+            .map(|commit| commit.parents.iter().filter_map(|parent| index.get(parent).copied()).collect())
+            .collect();
+        let rank = topological_rank(&parents);
+
+        Self {
+            commits,
+            index,
+            parents,
+            rank,
+        }
+    }
+
+    /// Check whether a commit, given by full hash, is part of this history
+    pub fn contains(&self, hash: &str) -> bool {
+        self.index.contains_key(hash)
+    }
+
+    /// Get the metadata of a commit in this history, given by full hash
+    pub fn metadata(&self, hash: &str) -> Option<&CommitMetadata> {
+        self.index.get(hash).map(|&i| &self.commits[i])
+    }
+
+    /// All commits, newest first
+    pub fn commits(&self) -> &[CommitMetadata] {
+        &self.commits
+    }
+
+    /// Number of commits in this history
+    pub fn len(&self) -> usize {
+        self.commits.len()
+    }
+
+    /// Check whether this history has no commits
+    pub fn is_empty(&self) -> bool {
+        self.commits.is_empty()
+    }
+
+    /// Full hash of the commit at a position
+    pub fn hash_at(&self, position: usize) -> &str {
+        &self.commits[position].hash
+    }
+
+    /// Position of a commit, given by full hash
+    pub fn position(&self, hash: &str) -> Option<usize> {
+        self.index.get(hash).copied()
+    }
+
+    /// Topological rank of the commit at a position, a commit always ranks lower than its parents
+    pub fn rank_at(&self, position: usize) -> usize {
+        self.rank[position]
+    }
+
+    /// Check whether the commit at `ancestor` is reachable from the commit at `descendant`
+    ///
+    /// A commit counts as its own ancestor. Only commits ranked between the two
+    /// are visited, so checks between nearby commits stay cheap.
+    pub fn is_ancestor_at(&self, ancestor: usize, descendant: usize) -> bool {
+        if ancestor == descendant {
+            return true;
+        }
+        if self.rank[ancestor] < self.rank[descendant] {
+            return false;
+        }
+
+        let mut seen = HashSet::new();
+        let mut stack = vec![descendant];
+        while let Some(i) = stack.pop() {
+            for &parent in &self.parents[i] {
+                if parent == ancestor {
+                    return true;
+                }
+                if self.rank[parent] < self.rank[ancestor] && seen.insert(parent) {
+                    stack.push(parent);
+                }
+            }
+        }
+        false
+    }
+
+    /// Collect the commit and all its ancestors that are part of this history
+    fn ancestors_of(&self, hash: &str) -> HashSet<usize> {
+        let mut seen = HashSet::new();
+        let mut stack: Vec<usize> = self.position(hash).into_iter().collect();
+        while let Some(i) = stack.pop() {
+            if seen.insert(i) {
+                stack.extend(&self.parents[i]);
+            }
+        }
+        seen
+    }
+}
+
+/// Rank commits so that every commit ranks lower than all of its parents
+fn topological_rank(parents: &[Vec<usize>]) -> Vec<usize> {
+    // Depth-first post-order visits parents before children, so reversing it
+    // gives an order where children come first
+    let mut post_order = Vec::with_capacity(parents.len());
+    let mut visited = vec![false; parents.len()];
+    for root in 0..parents.len() {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+        let mut stack = vec![(root, 0)];
+        while let Some((node, next_parent)) = stack.last_mut() {
+            if let Some(&parent) = parents[*node].get(*next_parent) {
+                *next_parent += 1;
+                if !visited[parent] {
+                    visited[parent] = true;
+                    stack.push((parent, 0));
+                }
+            } else {
+                post_order.push(*node);
+                stack.pop();
+            }
+        }
+    }
+
+    let mut rank = vec![0; parents.len()];
+    for (i, &node) in post_order.iter().rev().enumerate() {
+        rank[node] = i;
+    }
+    rank
+}
+
+impl Repository {
+    /// Open the repository containing the current directory, or the one `GIT_DIR` names
+    pub fn open() -> Result<Self> {
+        let force_subprocess = std::env::var("GIT_BP_BACKEND")
+            .is_ok_and(|backend| backend == "subprocess");
+
+        if force_subprocess {
+            debug!("GIT_BP_BACKEND=subprocess, using git subprocesses");
+        } else {
+            // GIT_DIR and GIT_WORK_TREE have to be honored like the git subprocesses do
+            match gix::discover_with_environment_overrides(".") {
+                Ok(repo) => return Ok(Self::from_gix(repo)),
+                Err(err) => {
+                    debug!("Could not open repository in-process, falling back to git subprocesses: {}", err);
+                }
+            }
+        }
+
+        let args = ["rev-parse", "--show-object-format"];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        // Git versions predating SHA-256 support don't know the option and only handle SHA-1
+        let object_format = if output.status.success() {
+            let name = String::from_utf8_lossy(&output.stdout);
+            ObjectFormat::from_name(&name)
+                .ok_or_else(|| Error::UnsupportedObjectFormat(name.trim().to_string()))?
+        } else {
+            ObjectFormat::Sha1
+        };
+
+        Ok(Self::new(None, object_format))
+    }
+
+    /// Use a repository opened through gix
+    pub fn from_gix(repo: gix::Repository) -> Self {
+        let object_format = ObjectFormat::from_name(&repo.object_hash().to_string())
+            .unwrap_or_default();
+        Self::new(Some(repo), object_format)
+    }
+
+    fn new(gix: Option<gix::Repository>, object_format: ObjectFormat) -> Self {
+        debug!("Repository uses {:?} object names", object_format);
+        Self {
+            gix,
+            object_format,
+            histories: RefCell::new(HashMap::new()),
+            ranges: RefCell::new(HashMap::new()),
+            range_metadata: RefCell::new(HashMap::new()),
+            trailer_indexes: RefCell::new(HashMap::new()),
+            patch_ids: RefCell::new(HashMap::new()),
+            commit_patch_ids: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Read the last value of a git config key, like `git config --get`
+    pub fn config_string(&self, key: &str) -> Result<Option<String>> {
+        Ok(config(&["--get", key])?.pop())
+    }
+
+    /// Read all values of a multi-valued git config key, like `git config --get-all`
+    pub fn config_strings(&self, key: &str) -> Result<Vec<String>> {
+        config(&["--get-all", key])
+    }
+
+    /// Read a boolean git config key, accepting every spelling git does (yes, on, 1, ...)
+    pub fn config_bool(&self, key: &str) -> Result<Option<bool>> {
+        Ok(config(&["--type=bool", "--get", key])?.pop().map(|value| value == "true"))
+    }
+
+    /// Resolve a revision that has to name a commit, telling unknown revisions from ambiguous hashes
+    pub fn resolve_commit(&self, rev: &str) -> Result<String> {
+        if let Some(hash) = self.resolve(rev)? {
+            return Ok(hash);
+        }
+
+        if self.object_format.is_hash_like(rev) {
+            let candidates = self.commits_with_prefix(rev)?;
+            if candidates.len() > 1 {
+                return Err(Error::AmbiguousHash {
+                    hash: rev.to_string(),
+                    candidates,
+                });
+            }
+        }
+        Err(Error::CommitNotFound(rev.to_string()))
+    }
+
+    /// Run git and return its output
+    pub fn git_output(&self, args: &[&str]) -> Result<String> {
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(Error::git_failed(args, &output));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Run git with the given input, returning its output
+    pub fn git_with_input(&self, args: &[&str], input: &str) -> Result<String> {
+        debug!("Running command: git {}", args.join(" "));
+        let mut child = Command::new("git")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().ok_or_else(|| Error::missing_pipe(args, "input"))?.write_all(input.as_bytes())?;
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::git_failed(args, &output));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Run git with the terminal attached, so its progress and conflicts reach the user, returning whether it succeeded
+    pub fn git_status(&self, args: &[&str]) -> Result<bool> {
+        self.git_status_with_env(args, &[])
+    }
+
+    /// Like [`Self::git_status`], with extra environment variables set for git
+    pub fn git_status_with_env(&self, args: &[&str], env: &[(&str, &str)]) -> Result<bool> {
+        let assignments: String = env.iter().map(|(name, value)| format!("{}={:?} ", name, value)).collect();
+        debug!("Running command: {}git {}", assignments, args.join(" "));
+        let status = Command::new("git")
+            .args(args)
+            .envs(env.iter().copied())
+            .status()?;
+
+        Ok(status.success())
+    }
+
+    /// List the commits whose hash starts with `prefix`, to tell ambiguous abbreviations from unknown ones
+    pub fn commits_with_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        let disambiguate = format!("--disambiguate={}", prefix);
+        let args = ["rev-parse", disambiguate.as_str()];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Ok(Vec::new());
+        }
+
+        // Other objects sharing the prefix don't make it ambiguous as a commit
+        let mut commits = Vec::new();
+        for hash in String::from_utf8_lossy(&output.stdout).lines() {
+            if self.resolve(hash)?.as_deref() == Some(hash) {
+                commits.push(hash.to_string());
+            }
+        }
+        Ok(commits)
+    }
+
+    /// Look up a commit, given by full hash, in the histories loaded so far
+    fn loaded_metadata(&self, hash: &str) -> Option<CommitMetadata> {
+        self.histories.borrow()
+            .values()
+            .find_map(|history| history.metadata(hash).cloned())
+    }
+
+    /// Load the commits of a branch from the cache, walking and storing only what is not cached yet
+    ///
+    /// Commits reachable from the tip of any cached ref are taken from the
+    /// cache, so a branch that moved forward only walks the commits made since,
+    /// and a branch forked from a cached one only the commits made on it.
+    fn load_cached_commits(&self, ref_name: &str, tip_hash: &str) -> Result<Vec<CommitMetadata>> {
+        let cache = Cache::new(self.git_path("bp-cache")?);
+
+        // The commits only the old tip of a rewritten branch reaches are garbage
+        let previous = cache.tip(ref_name);
+        let rewritten = match &previous {
+            Some(previous) => previous != tip_hash && !self.is_ancestor_commit(previous, tip_hash)?,
+            None => false,
+        };
+
+        // The cache only speeds things up, failing to write it is not an error
+        let lock = match cache.lock_shared() {
+            Ok(lock) => lock,
+            Err(err) => {
+                debug!("Could not lock the cache: {}", err);
+                return self.walk(tip_hash, &[]);
+            }
+        };
+
+        if !cache.has_segment(tip_hash) {
+            // A branch that moved forward builds on its old tip, a new or rewritten
+            // one on the related branches among those cached most recently
+            let bases = match &previous {
+                Some(previous) if !rewritten => vec![previous.clone()],
+                _ => {
+                    let mut bases = Vec::new();
+                    let candidates = cache.tips()?
+                        .into_iter()
+                        .filter(|tip| tip != tip_hash && !(rewritten && previous.as_ref() == Some(tip)))
+                        .take(MAX_BASE_CANDIDATES);
+                    for tip in candidates {
+                        if self.merge_base(&tip, tip_hash)?.is_some() {
+                            bases.push(tip);
+                        }
+                    }
+                    bases
+                }
+            };
+            debug!("Walking {} ({}), taking what {} cached tip(s) reach from the cache", ref_name, tip_hash, bases.len());
+            let commits = self.walk(tip_hash, &bases)?;
+
+            let trailers = SegmentTrailers::build(&commits, self.object_format);
+            if let Err(err) = cache.store(tip_hash, &bases, &commits, &trailers) {
+                debug!("Could not store cached history of {}: {}", ref_name, err);
+                return self.walk(tip_hash, &[]);
+            }
+        }
+
+        if previous.as_deref() != Some(tip_hash) {
+            if let Err(err) = cache.set_tip(ref_name, tip_hash) {
+                debug!("Could not store cached tip of {}: {}", ref_name, err);
+            }
+            // Pruning waits for every run storing segments, this one included
+            drop(lock);
+            if rewritten {
+                debug!("{} was rewritten, pruning what only its old tip needed", ref_name);
+                if let Err(err) = cache.prune() {
+                    debug!("Could not prune the cache: {}", err);
+                }
+            }
+        }
+
+        match cache.load(tip_hash) {
+            Some(commits) => {
+                debug!("Using cached history of {}", ref_name);
+                Ok(commits)
+            }
+            None => {
+                debug!("Cached history of {} is unusable, walking it", ref_name);
+                self.walk(tip_hash, &[])
+            }
+        }
+    }
+
+    /// Walk commits reachable from `tip` but from none of `hidden`, newest first
+    fn walk(&self, tip: &str, hidden: &[String]) -> Result<Vec<CommitMetadata>> {
+        match &self.gix {
+            Some(repo) => walk_in_process(repo, tip, hidden),
+            None => walk_with_subprocess(tip, hidden),
+        }
+    }
+
+    /// Check whether `ancestor` is reachable from `descendant` without loading any history
+    fn is_ancestor_commit(&self, ancestor: &str, descendant: &str) -> Result<bool> {
+        if let Some(repo) = &self.gix {
+            let (Ok(ancestor_id), Ok(descendant_id)) = (
+                gix::ObjectId::from_hex(ancestor.as_bytes()),
+                gix::ObjectId::from_hex(descendant.as_bytes()),
+            ) else {
+                return Ok(false);
+            };
+            // A missing commit (e.g. garbage collected after a force-push) is never an ancestor
+            return Ok(repo.merge_base(ancestor_id, descendant_id)
+                .is_ok_and(|base| base.is_some_and(|base| base.detach() == ancestor_id)));
+        }
+
+        let args = ["merge-base", "--is-ancestor", ancestor, descendant];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        Ok(output.status.success())
+    }
+
+    /// Get the full name of the branch a revision refers to, following symbolic refs like HEAD
+    fn ref_name(&self, rev: &str) -> Result<Option<String>> {
+        if let Some(repo) = &self.gix {
+            let Ok(Some(mut reference)) = repo.try_find_reference(rev) else {
+                return Ok(None);
+            };
+            while let Some(target) = reference.follow() {
+                reference = target.map_err(Error::gix)?;
+            }
+            let name = reference.name().as_bstr().to_string();
+            return Ok(name.starts_with("refs/").then_some(name));
+        }
+
+        let args = ["rev-parse", "--symbolic-full-name", rev];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok((output.status.success() && name.starts_with("refs/")).then_some(name))
+    }
+
+    /// Resolve a path inside the git directory shared by all worktrees, like `git rev-parse --git-path`
+    pub fn git_path(&self, name: &str) -> Result<PathBuf> {
+        if let Some(repo) = &self.gix {
+            return Ok(repo.common_dir().join(name));
+        }
+
+        let args = ["rev-parse", "--git-path", name];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(Error::git_failed(&args, &output));
+        }
+
+        Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+    }
+
+    /// Compute the patch-ids of many commits, given by full hash, with a single git run
+    ///
+    /// Later [`Self::patch_id`] calls for these commits are answered without running git.
+    pub fn load_patch_ids(&self, hashes: &[String]) -> Result<()> {
+        let missing: Vec<&str> = hashes.iter()
+            .filter(|hash| !self.commit_patch_ids.borrow().contains_key(hash.as_str()))
+            .map(String::as_str)
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let input: String = missing.iter().map(|hash| format!("{}\n", hash)).collect();
+        let patch_ids = patch_ids(&["--no-walk=unsorted", "--stdin"], Some(&input))?;
+
+        let mut commit_patch_ids = self.commit_patch_ids.borrow_mut();
+        for hash in missing {
+            commit_patch_ids.insert(hash.to_string(), None);
+        }
+        for (patch_id, hash) in patch_ids {
+            commit_patch_ids.insert(hash, Some(patch_id));
+        }
+        Ok(())
+    }
+
+    /// Get the diff a commit introduces against its first parent, as a patch
+    pub fn patch(&self, rev: &str) -> Result<String> {
+        let args = ["diff-tree", "-p", "--root", "--no-commit-id", "--no-color", "--no-ext-diff", rev];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(Error::git_failed(&args, &output));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Get the diff from the first parent of `first` to `last` in the given paths, as one patch
+    ///
+    /// This is the change of all commits from `first` up to `last` together,
+    /// e.g. of upstream commits that were squashed into one backport.
+    pub fn squashed_patch(&self, first: &str, last: &str, paths: &[String]) -> Result<String> {
+        let from = match self.metadata(first)?.and_then(|metadata| metadata.parents.into_iter().next()) {
+            Some(parent) => parent,
+            // A root commit is compared with the empty tree
+            None => self.git_with_input(&["hash-object", "-t", "tree", "--stdin"], "")?.trim().to_string(),
+        };
+        let mut args = vec!["diff-tree", "-p", "--no-color", "--no-ext-diff", &from, last, "--"];
+        args.extend(paths.iter().map(String::as_str));
+        self.git_output(&args)
+    }
+
+    /// List the lines a commit changes, as line ranges in the files of its first parent
+    ///
+    /// Lines that are removed or modified are listed as they are. For pure
+    /// insertions the line the new lines are inserted after is listed instead,
+    /// as the commit needs that line to apply. Root and merge commits have no
+    /// pre-image and yield nothing.
+    pub fn pre_image_lines(&self, rev: &str) -> Result<Vec<FileLines>> {
+        let args = ["diff-tree", "-p", "-U0", "--no-color", "--no-ext-diff", "--no-renames", "--no-prefix", rev];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(Error::git_failed(&args, &output));
+        }
+
+        let mut files: Vec<FileLines> = Vec::new();
+        let mut current = None;
+        let mut in_header = false;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if line.starts_with("diff --git ") {
+                // Removed lines starting with "-- " look like headers, so only parse those before the first hunk
+                in_header = true;
+                current = None;
+            } else if let Some(path) = line.strip_prefix("--- ").filter(|_| in_header) {
+                // New files have no pre-image to depend on
+                current = (path != "/dev/null").then(|| unquote_path(path));
+                if let Some(path) = &current {
+                    files.push(FileLines {
+                        path: path.clone(),
+                        ranges: Vec::new(),
+                    });
+                }
+            } else if let Some(hunk) = line.strip_prefix("@@ -") {
+                in_header = false;
+                if current.is_none() {
+                    continue;
+                }
+                let old = hunk.split_whitespace().next().unwrap_or("");
+                let number = |text: &str| text.parse::<usize>()
+                    .map_err(|_| Error::InvalidHunkHeader {
+                        rev: rev.to_string(),
+                        line: line.to_string(),
+                    });
+                let (start, count) = match old.split_once(',') {
+                    Some((start, count)) => (number(start)?, number(count)?),
+                    None => (number(old)?, 1),
+                };
+                let range = if count == 0 { (start, 1) } else { (start, count) };
+                if range.0 > 0 {
+                    files.last_mut().expect("a file precedes its hunks").ranges.push(range);
+                }
+            }
+        }
+
+        files.retain(|file| !file.ranges.is_empty());
+        Ok(files)
+    }
+
+    /// Find the commits that last touched the given line ranges of a file at `rev`, like `git blame`
+    pub fn blame(&self, rev: &str, path: &str, ranges: &[(usize, usize)]) -> Result<HashSet<String>> {
+        let mut args = vec!["blame".to_string(), "--porcelain".to_string()];
+        for (start, count) in ranges {
+            args.push(format!("-L{},+{}", start, count));
+        }
+        args.extend([rev.to_string(), "--".to_string(), path.to_string()]);
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(&args)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(Error::git_failed(&args, &output));
+        }
+
+        // Each blamed line starts with a header of the form "<hash> <orig line> <final line> [<count>]"
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|word| self.object_format.is_full_hash(word))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// List the files changed by any commit in `exclude..tip`, sorted
+    pub fn files_changed_in_range(&self, exclude: &str, tip: &str) -> Result<Vec<String>> {
+        let range = format!("{}..{}", exclude, tip);
+        let args = ["log", "--no-merges", "--format=", "--name-only", &range];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(Error::git_failed(&args, &output));
+        }
+
+        let mut files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    /// List the non-merge commits in `exclude..tip` that change any of the given paths, oldest first
+    pub fn commits_touching(&self, exclude: &str, tip: &str, paths: &[String]) -> Result<Vec<String>> {
+        // Paths are passed on stdin, as our own changes can touch more files than fit on a command line
+        let input = format!("{}..{}\n--\n{}", exclude, tip,
+                            paths.iter().map(|path| format!("{}\n", path)).collect::<String>());
+        let args = ["rev-list", "--reverse", "--no-merges", "--stdin"];
+        debug!("Running command: git {} <<< {}..{} -- {}", args.join(" "), exclude, tip, paths.join(" "));
+        let mut child = Command::new("git")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        child.stdin.take().ok_or_else(|| Error::missing_pipe(&args, "input"))?.write_all(input.as_bytes())?;
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            return Err(Error::git_failed(&args, &output));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+impl GitBackend for Repository {
+    fn object_format(&self) -> ObjectFormat {
+        self.object_format
+    }
+
+    fn resolve(&self, rev: &str) -> Result<Option<String>> {
+        let spec = format!("{}^{{commit}}", rev);
+
+        if let Some(repo) = &self.gix {
+            if let Ok(id) = repo.rev_parse_single(spec.as_str()) {
+                return Ok(Some(id.detach().to_string()));
+            }
+            // gix parses abbreviated hashes as SHA-1 prefixes, so look those up by hand in other formats
+            if self.object_format != ObjectFormat::Sha1 && self.object_format.is_hash_like(rev) {
+                return Ok(resolve_abbreviated(repo, self.object_format, rev));
+            }
+            return Ok(None);
+        }
+
+        let args = ["rev-parse", "--verify", "-q", &spec];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok((!hash.is_empty()).then_some(hash))
+    }
+
+    /// Get the metadata of a commit, from a loaded history when possible
+    fn metadata(&self, rev: &str) -> Result<Option<CommitMetadata>> {
+        if let Some(metadata) = self.loaded_metadata(rev) {
+            return Ok(Some(metadata));
+        }
+
+        let Some(hash) = self.resolve(rev)? else {
+            return Ok(None);
+        };
+        if let Some(metadata) = self.loaded_metadata(&hash) {
+            return Ok(Some(metadata));
+        }
+
+        if let Some(repo) = &self.gix {
+            let commit = repo.find_commit(gix::ObjectId::from_hex(hash.as_bytes()).map_err(Error::gix)?)
+                .map_err(Error::gix)?;
+            let parents = commit.parent_ids().map(|id| id.to_string()).collect();
+            let message = String::from_utf8_lossy(commit.message_raw_sloppy());
+            return Ok(Some(CommitMetadata::parse(hash, parents, &message)));
+        }
+
+        let args = ["log", "-z", "--format=%H %P%n%B", "-n", "1", &hash];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        Ok(parse_log_records(&String::from_utf8_lossy(&output.stdout)).into_iter().next())
+    }
+
+    /// Get every commit reachable from a tip, walking the history only once per tip
+    ///
+    /// Histories of branches are also kept in the on-disk cache, so later runs
+    /// only have to walk the commits added since.
+    fn history(&self, tip: &str) -> Result<Rc<History>> {
+        let tip_hash = self.resolve_commit(tip)?;
+
+        if let Some(history) = self.histories.borrow().get(&tip_hash) {
+            return Ok(history.clone());
+        }
+
+        debug!("Loading history of {} ({})", tip, tip_hash);
+        let commits = match self.ref_name(tip)? {
+            Some(ref_name) => self.load_cached_commits(&ref_name, &tip_hash)?,
+            None => self.walk(&tip_hash, &[])?,
+        };
+        let history = Rc::new(History::new(commits));
+        debug!("Loaded {} commits reachable from {}", history.len(), tip);
+
+        self.histories.borrow_mut().insert(tip_hash, history.clone());
+        Ok(history)
+    }
+
+    /// Get the trailer index of a branch, building it on first use
+    fn trailer_index(&self, tip: &str) -> Result<Rc<TrailerIndex>> {
+        let tip_hash = self.resolve_commit(tip)?;
+
+        if let Some(index) = self.trailer_indexes.borrow().get(&tip_hash) {
+            return Ok(index.clone());
+        }
+
+        let history = self.history(tip)?;
+        let cache = Cache::new(self.git_path("bp-cache")?);
+        let index = match cache.load_trailers(&tip_hash) {
+            Some(trailers) => {
+                debug!("Assembled trailer index of {} ({}) from the cache", tip, tip_hash);
+                TrailerIndex::from_segments(history, self.object_format, trailers)
+            }
+            None => {
+                debug!("Built trailer index of {} ({})", tip, tip_hash);
+                TrailerIndex::build(history, self.object_format)
+            }
+        };
+        let index = Rc::new(index);
+
+        self.trailer_indexes.borrow_mut().insert(tip_hash, index.clone());
+        Ok(index)
+    }
+
+    /// Ask git, which only walks as far back as the merge base
+    fn merge_base(&self, one: &str, two: &str) -> Result<Option<String>> {
+        let one = self.resolve_commit(one)?;
+        let two = self.resolve_commit(two)?;
+
+        if let Some(repo) = &self.gix {
+            let one = gix::ObjectId::from_hex(one.as_bytes()).map_err(Error::gix)?;
+            let two = gix::ObjectId::from_hex(two.as_bytes()).map_err(Error::gix)?;
+            let base = repo.merge_base(one, two).map_err(Error::gix)?;
+            return Ok(base.map(|base| base.detach().to_string()));
+        }
+
+        let args = ["merge-base", &one, &two];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        // git exits with 1 when the commits have no common ancestor
+        let base = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok((output.status.success() && !base.is_empty()).then_some(base))
+    }
+
+    /// Walk only `base..tip`, leaving the history before `base` unloaded
+    fn history_since(&self, base: &str, tip: &str) -> Result<Rc<History>> {
+        let tip_hash = self.resolve_commit(tip)?;
+        let base_hash = self.resolve_commit(base)?;
+
+        debug!("Loading history of {} ({}) since {}", tip, tip_hash, base_hash);
+        let mut commits = self.walk(&tip_hash, std::slice::from_ref(&base_hash))?;
+        commits.extend(self.metadata(&base_hash)?);
+        debug!("Loaded {} commits", commits.len());
+        Ok(Rc::new(History::new(commits)))
+    }
+
+    /// Check ancestry on the loaded history of `tip`, or let git walk to the merge base
+    fn is_ancestor(&self, commit: &str, tip: &str) -> Result<bool> {
+        let (Some(commit), Some(tip)) = (self.resolve(commit)?, self.resolve(tip)?) else {
+            return Ok(false);
+        };
+        if let Some(history) = self.histories.borrow().get(&tip) {
+            return Ok(history.contains(&commit));
+        }
+        self.is_ancestor_commit(&commit, &tip)
+    }
+
+    /// List `exclude..tip` from the loaded history of `tip`, or walk only the range
+    fn range(&self, exclude: &str, tip: &str) -> Result<Rc<Vec<String>>> {
+        let tip_hash = self.resolve_commit(tip)?;
+        let exclude_hash = self.resolve_commit(exclude)?;
+
+        let key = format!("{}..{}", exclude_hash, tip_hash);
+        if let Some(range) = self.ranges.borrow().get(&key) {
+            return Ok(range.clone());
+        }
+
+        let history = self.histories.borrow().get(&tip_hash).cloned();
+        let range: Vec<String> = match history {
+            Some(history) if history.contains(&exclude_hash) => {
+                let hidden = history.ancestors_of(&exclude_hash);
+                (0..history.len())
+                    .filter(|position| !hidden.contains(position))
+                    .map(|position| history.hash_at(position).to_string())
+                    .collect()
+            }
+            // The walk stops where the histories of both ends meet
+            _ => self.range_metadata(exclude, tip)?
+                .iter()
+                .map(|commit| commit.hash.clone())
+                .collect(),
+        };
+
+        let range = Rc::new(range);
+        self.ranges.borrow_mut().insert(key, range.clone());
+        Ok(range)
+    }
+
+    fn range_metadata(&self, exclude: &str, tip: &str) -> Result<Rc<Vec<CommitMetadata>>> {
+        let tip_hash = self.resolve_commit(tip)?;
+        let exclude_hash = self.resolve_commit(exclude)?;
+
+        let key = format!("{}..{}", exclude_hash, tip_hash);
+        if let Some(commits) = self.range_metadata.borrow().get(&key) {
+            return Ok(commits.clone());
+        }
+
+        debug!("Walking {}..{}", exclude, tip);
+        let commits = Rc::new(self.walk(&tip_hash, &[exclude_hash])?);
+        self.range_metadata.borrow_mut().insert(key, commits.clone());
+        Ok(commits)
+    }
+
+    fn patch_id(&self, rev: &str) -> Result<Option<String>> {
+        let hash = self.resolve_commit(rev)?;
+
+        if !self.commit_patch_ids.borrow().contains_key(&hash) {
+            self.load_patch_ids(std::slice::from_ref(&hash))?;
+        }
+        Ok(self.commit_patch_ids.borrow().get(&hash).cloned().flatten())
+    }
+
+    fn patch_ids(&self, exclude: &str, tip: &str) -> Result<Rc<HashMap<String, String>>> {
+        let tip_hash = self.resolve_commit(tip)?;
+        let exclude_hash = self.resolve_commit(exclude)?;
+
+        let key = format!("{}..{}", exclude_hash, tip_hash);
+        if let Some(patch_ids) = self.patch_ids.borrow().get(&key) {
+            return Ok(patch_ids.clone());
+        }
+
+        let patch_ids = Rc::new(patch_ids(&[&key], None)?);
+        debug!("Computed {} patch-ids for {}..{}", patch_ids.len(), exclude, tip);
+        self.commit_patch_ids.borrow_mut()
+            .extend(patch_ids.iter().map(|(patch_id, hash)| (hash.clone(), Some(patch_id.clone()))));
+        self.patch_ids.borrow_mut().insert(key, patch_ids.clone());
+        Ok(patch_ids)
+    }
+
+    fn changed_files(&self, rev: &str) -> Result<Vec<String>> {
+        let args = ["diff-tree", "--no-commit-id", "--name-only", "-r", "--root", rev];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(Error::git_failed(&args, &output));
+        }
+
+        let mut files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    fn commit_time(&self, rev: &str) -> Result<Option<i64>> {
+        let Some(hash) = self.resolve(rev)? else {
+            return Ok(None);
+        };
+
+        if let Some(repo) = &self.gix {
+            let commit = repo.find_commit(gix::ObjectId::from_hex(hash.as_bytes()).map_err(Error::gix)?)
+                .map_err(Error::gix)?;
+            return Ok(Some(commit.time().map_err(Error::gix)?.seconds));
+        }
+
+        let args = ["log", "-1", "--format=%ct", &hash];
+        debug!("Running command: git {}", args.join(" "));
+        let output = Command::new("git")
+            .args(args)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(Error::git_failed(&args, &output));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+    }
+}
+
+/// Undo the C-style quoting git applies to paths with unusual characters
+fn unquote_path(path: &str) -> String {
+    let Some(quoted) = path.strip_prefix('"').and_then(|path| path.strip_suffix('"')) else {
+        return path.to_string();
+    };
+
+    let mut bytes = Vec::new();
+    let mut chars = quoted.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some(digit @ '0'..='7') => {
+                // Octal escapes encode the raw bytes of non-ASCII characters
+                let mut value = digit.to_digit(8).expect("octal digit");
+                for _ in 0..2 {
+                    if let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) {
+                        value = value * 8 + digit;
+                        chars.next();
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            Some(other) => bytes.push(other as u8),
+            None => {}
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Run `git log -p <args> | git patch-id --stable` and map each patch-id to its commit
+fn patch_ids(log_args: &[&str], stdin: Option<&str>) -> Result<HashMap<String, String>> {
+    let mut args = vec!["log", "-p", "--no-color", "--no-ext-diff", "--format=commit %H"];
+    args.extend(log_args);
+    debug!("Running command: git {} | git patch-id --stable", args.join(" "));
+    let mut log = Command::new("git")
+        .args(&args)
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // git log reads all revisions before it starts printing, so this can't block on its output
+    if let Some(stdin) = stdin {
+        log.stdin.take().ok_or_else(|| Error::missing_pipe(&args, "input"))?.write_all(stdin.as_bytes())?;
+    }
+
+    let output = Command::new("git")
+        .args(["patch-id", "--stable"])
+        .stdin(log.stdout.take().ok_or_else(|| Error::missing_pipe(&args, "output"))?)
+        .output()?;
+
+    if !log.wait()?.success() {
+        return Err(Error::git_exited(&args));
+    }
+    if !output.status.success() {
+        return Err(Error::git_failed(&["patch-id", "--stable"], &output));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(patch_id, commit)| (patch_id.to_string(), commit.to_string()))
+        .collect())
+}
+
+/// Run `git config` with the given arguments, returning the values it prints
+///
+/// A missing key is not an error and yields no values.
+fn config(args: &[&str]) -> Result<Vec<String>> {
+    let mut args = args.to_vec();
+    args.insert(0, "config");
+    debug!("Running command: git {}", args.join(" "));
+    let output = Command::new("git")
+        .args(&args)
+        .output()?;
+
+    // git config exits with 1 when the key is not set
+    if output.status.code() == Some(1) {
+        return Ok(Vec::new());
+    }
+    if !output.status.success() {
+        return Err(Error::git_failed(&args, &output));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// Resolve an abbreviated hash to the commit it names, like `git rev-parse <prefix>^{commit}`
+fn resolve_abbreviated(repo: &gix::Repository, object_format: ObjectFormat, prefix: &str) -> Option<String> {
+    let padded = format!("{:0<width$}", prefix, width = object_format.hex_len());
+    let prefix = gix::hash::Prefix::new(&gix::ObjectId::from_hex(padded.as_bytes()).ok()?, prefix.len()).ok()?;
+
+    // Ambiguous prefixes don't resolve, just like in git
+    let id = repo.objects.lookup_prefix(prefix, None).ok()??.ok()?;
+    let commit = repo.find_object(id).ok()?.peel_to_commit().ok()?;
+    Some(commit.id.to_string())
+}
+
+/// Walk the history of a tip with gix
+fn walk_in_process(repo: &gix::Repository, tip: &str, hidden: &[String]) -> Result<Vec<CommitMetadata>> {
+    let tip = gix::ObjectId::from_hex(tip.as_bytes()).map_err(Error::gix)?;
+    let hidden = hidden.iter()
+        .map(|hidden| gix::ObjectId::from_hex(hidden.as_bytes()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(Error::gix)?;
+    let walk = repo.rev_walk([tip])
+        .with_hidden(hidden)
+        .sorting(gix::revision::walk::Sorting::ByCommitTime(Default::default()))
+        .all()
+        .map_err(Error::gix)?;
+
+    let mut commits = Vec::new();
+    for info in walk {
+        let info = info.map_err(Error::gix)?;
+        let commit = info.object().map_err(Error::gix)?;
+        commits.push(CommitMetadata::parse(
+            info.id.to_string(),
+            info.parent_ids.iter().map(|id| id.to_string()).collect(),
+            &String::from_utf8_lossy(commit.message_raw_sloppy()),
+        ));
+    }
+
+    Ok(commits)
+}
+
+/// Walk the history of a tip with a single `git log` process
+fn walk_with_subprocess(tip: &str, hidden: &[String]) -> Result<Vec<CommitMetadata>> {
+    let mut args = vec!["log".to_string(), "-z".to_string(), "--format=%H %P%n%B".to_string(), tip.to_string()];
+    args.extend(hidden.iter().map(|hidden| format!("^{}", hidden)));
+    debug!("Running command: git {}", args.join(" "));
+    let output = Command::new("git")
+        .args(&args)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::git_failed(&args, &output));
+    }
+
+    Ok(parse_log_records(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse the output of `git log -z --format="%H %P%n%B"`
+fn parse_log_records(stdout: &str) -> Vec<CommitMetadata> {
+    let mut commits = Vec::new();
+    for record in stdout.split('\0') {
+        let record = record.trim_start_matches('\n');
+        if record.is_empty() {
+            continue;
+        }
+
+        let (header, message) = record.split_once('\n').unwrap_or((record, ""));
+        let mut ids = header.split_whitespace();
+        let Some(hash) = ids.next() else {
+            continue;
+        };
+        commits.push(CommitMetadata::parse(
+            hash.to_string(),
+            ids.map(str::to_string).collect(),
+            message,
+        ));
+    }
+    commits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::utils::commits::CommitInfo;
+
+    /// Run git in `dir` and return its trimmed output
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    fn commit(dir: &Path, message: &str) -> String {
+        git(dir, &["commit", "-q", "--allow-empty", "-m", message]);
+        git(dir, &["rev-parse", "HEAD"])
+    }
+
+    /// A SHA-256 repository with a commit, a fix and a revert of it, all on `up`
+    struct Sha256Fixture {
+        dir: tempfile::TempDir,
+        feature: String,
+        fix: String,
+        revert: String,
+    }
+
+    fn sha256_fixture() -> Sha256Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "-q", "--object-format=sha256", "-b", "up"]);
+
+        let feature = commit(dir.path(), "feature A\n\nChange-Id: I0123456789abcdef0123456789abcdef01234567");
+        let fix = commit(dir.path(), &format!("fix A\n\nFixes: {} (\"feature A\")", &feature[..16]));
+        let revert = commit(dir.path(), &format!("Revert \"feature A\"\n\nThis reverts commit {}.", feature));
+
+        Sha256Fixture { dir, feature, fix, revert }
+    }
+
+    fn open(dir: &Path) -> Repository {
+        Repository::from_gix(gix::open(dir).unwrap())
+    }
+
+    #[test]
+    fn detects_sha256_object_format() {
+        let fixture = sha256_fixture();
+        let repo = open(fixture.dir.path());
+
+        assert_eq!(repo.object_format(), ObjectFormat::Sha256);
+        assert_eq!(fixture.feature.len(), 64);
+        assert_eq!(repo.resolve("up").unwrap(), Some(fixture.revert.clone()));
+    }
+
+    #[test]
+    fn expands_abbreviated_sha256_hashes() {
+        let fixture = sha256_fixture();
+        let repo = open(fixture.dir.path());
+
+        let mut commit_info = CommitInfo::from_hash(fixture.fix[..12].to_string());
+        commit_info.fetch_change_id_if_missing(&repo).unwrap();
+        commit_info.fetch_title_if_missing(&repo).unwrap();
+
+        assert_eq!(commit_info.hash, fixture.fix);
+        assert_eq!(commit_info.title.as_deref(), Some("fix A"));
+    }
+
+    #[test]
+    fn finds_trailers_pointing_at_sha256_commits() {
+        let fixture = sha256_fixture();
+        let repo = open(fixture.dir.path());
+        let index = repo.trailer_index("up").unwrap();
+
+        let fixes: Vec<String> = index.fixes_of(&fixture.feature).into_iter().map(|fix| fix.hash).collect();
+        assert_eq!(fixes, vec![fixture.fix.clone()]);
+        assert_eq!(index.reverts_of(&fixture.feature), vec![fixture.revert.clone()]);
+        assert_eq!(index.find_by_change_id("I0123456789abcdef0123456789abcdef01234567"),
+                   Some(fixture.feature.clone()));
+        // Abbreviations shorter than git's minimum never match
+        assert!(index.fixes_of(&fixture.feature[..6]).is_empty());
+    }
+
+    /// Number of segments the cached history of a branch is stored in
+    fn cached_segments(dir: &Path, branch: &str) -> usize {
+        let cache = Cache::new(dir.join(".git/bp-cache"));
+        let ref_name = format!("refs/heads/{}", branch);
+        cache.entries().unwrap().iter().find(|entry| entry.ref_name == ref_name).map_or(0, |entry| entry.segments)
+    }
+
+    fn hashes(history: &History) -> Vec<String> {
+        history.commits().iter().map(|commit| commit.hash.clone()).collect()
+    }
+
+    #[test]
+    fn caches_branches_in_shared_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        git(path, &["init", "-q", "-b", "up"]);
+        let root = commit(path, "root");
+        let forked = commit(path, "forked");
+        git(path, &["checkout", "-q", "-b", "bp"]);
+        let picked = commit(path, "picked");
+        git(path, &["checkout", "-q", "up"]);
+        let newer = commit(path, "newer");
+        assert_eq!(hashes(&open(path).history("up").unwrap()), vec![newer.clone(), forked.clone(), root.clone()]);
+
+        // The branch forked from up only stores its own commit
+        assert_eq!(hashes(&open(path).history("bp").unwrap()), vec![picked.clone(), forked.clone(), root.clone()]);
+        assert_eq!(cached_segments(path, "bp"), 2);
+
+        // Moving forward adds a segment with the new commit
+        let newest = commit(path, "newest");
+        assert_eq!(hashes(&open(path).history("up").unwrap()), vec![newest.clone(), newer.clone(), forked.clone(), root.clone()]);
+        assert_eq!(cached_segments(path, "up"), 2);
+
+        // A rewritten branch drops the segment only its old tip needed
+        git(path, &["reset", "-q", "--hard", &forked]);
+        let rewritten = commit(path, "rewritten");
+        assert_eq!(hashes(&open(path).history("up").unwrap()), vec![rewritten.clone(), forked, root]);
+        assert!(!path.join(format!(".git/bp-cache/segments/{}.json", newest)).exists());
+        assert_eq!(open(path).history("bp").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn assembles_trailer_index_from_cached_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        git(path, &["init", "-q", "-b", "up"]);
+        let feature = commit(path, "feature\n\nChange-Id: I0123456789abcdef0123456789abcdef01234567");
+        let fix = commit(path, &format!("fix\n\nFixes: {} (\"feature\")", &feature[..12]));
+        open(path).history("up").unwrap();
+
+        // The fix is in the older segment, the revert in the one added on top of it
+        let revert = commit(path, &format!("Revert \"feature\"\n\nThis reverts commit {}.", feature));
+        open(path).history("up").unwrap();
+        assert_eq!(cached_segments(path, "up"), 2);
+
+        let index = open(path).trailer_index("up").unwrap();
+        let fixes: Vec<String> = index.fixes_of(&feature).into_iter().map(|fix| fix.hash).collect();
+        assert_eq!(fixes, vec![fix]);
+        assert_eq!(index.reverts_of(&feature), vec![revert]);
+        assert_eq!(index.find_by_change_id("I0123456789abcdef0123456789abcdef01234567"), Some(feature));
+    }
+
+    #[test]
+    fn reads_sha256_commits_from_cache() {
+        let fixture = sha256_fixture();
+        open(fixture.dir.path()).history("up").unwrap();
+
+        // A fresh instance loads the history written by the first one
+        let repo = open(fixture.dir.path());
+        let history = repo.history("up").unwrap();
+        assert_eq!(history.len(), 3);
+        assert!(history.contains(&fixture.feature));
+        assert!(repo.is_ancestor(&fixture.feature[..7], "up").unwrap());
+    }
+}
@@ -0,0 +1,159 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//! Scripted fixture repositories for the integration tests
+
+// Each test binary uses only some of the helpers
+#![allow(dead_code)]
+
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A temporary git repository, isolated from the user's git configuration
+pub struct TestRepo {
+    dir: tempfile::TempDir,
+    /// Seconds since the first commit, so every commit is newer than the one before
+    clock: Cell<u64>,
+}
+
+impl TestRepo {
+    /// Create a repository whose `main` branch has a single commit adding `README`
+    pub fn new() -> Self {
+        let repo = Self {
+            dir: tempfile::tempdir().unwrap(),
+            clock: Cell::new(0),
+        };
+        repo.git(&["init", "-q", "-b", "main"]);
+        repo.commit("README", "hello\n", "init");
+        repo
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Build a command run in the repository with the environment every git and git-bp run gets
+    pub fn command(&self, program: impl AsRef<std::ffi::OsStr>) -> Command {
+        let time = format!("@{} +0000", 1_700_000_000 + self.clock.get());
+        let mut command = Command::new(program);
+        command.current_dir(self.path())
+            .env("HOME", self.path())
+            .env("GIT_CONFIG_GLOBAL", self.path().join(".gitconfig"))
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "Test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_AUTHOR_DATE", &time)
+            .env("GIT_COMMITTER_NAME", "Test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_DATE", &time)
+            .env("GIT_EDITOR", "true")
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_BP_BACKEND");
+        command
+    }
+
+    /// Run git and return its trimmed output, failing the test if it fails
+    pub fn git(&self, args: &[&str]) -> String {
+        self.clock.set(self.clock.get() + 1);
+        let output = self.command("git").args(args).output().unwrap();
+        assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// Write `content` to `file` and commit it, returning the hash of the new commit
+    pub fn commit(&self, file: &str, content: &str, message: &str) -> String {
+        self.write(file, content);
+        self.git(&["add", file]);
+        self.git(&["commit", "-q", "-m", message]);
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    /// Build a git-bp command, with its directory first on PATH so generated `git bp` commands find it
+    pub fn bp_command(&self, args: &[&str]) -> Command {
+        let binary = PathBuf::from(env!("CARGO_BIN_EXE_git-bp"));
+        let path = std::env::join_paths(
+            std::iter::once(binary.parent().unwrap().to_path_buf())
+                .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default())),
+        ).unwrap();
+        let mut command = self.command(&binary);
+        command.env("PATH", path).args(args);
+        command
+    }
+
+    /// Run git-bp in the repository
+    pub fn bp(&self, args: &[&str]) -> Output {
+        self.bp_command(args).output().unwrap()
+    }
+
+    /// Run git-bp and return its standard output, failing the test if it fails
+    pub fn bp_ok(&self, args: &[&str]) -> String {
+        let output = self.bp(args);
+        assert!(output.status.success(), "git-bp {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    pub fn write(&self, file: &str, content: &str) {
+        fs::write(self.path().join(file), content).unwrap();
+    }
+
+    pub fn read(&self, file: &str) -> String {
+        fs::read_to_string(self.path().join(file)).unwrap()
+    }
+}
+
+/// Hashes of the commits made by [`backport_fixture`]
+pub struct Fixture {
+    pub repo: TestRepo,
+    /// Upstream commits on `up`, oldest first
+    pub feature: String,
+    pub other: String,
+    pub fix: String,
+    pub fix_of_fix: String,
+    pub revert: String,
+    pub unrelated: String,
+}
+
+pub const FEATURE_CHANGE_ID: &str = "I1111111111111111111111111111111111111111";
+pub const OTHER_CHANGE_ID: &str = "I2222222222222222222222222222222222222222";
+pub const BACKPORT_CHANGE_ID: &str = "I3333333333333333333333333333333333333333";
+
+/// An upstream branch `up` and a backport branch `bp`, both forked from `main`
+///
+/// `up` has a feature, a fix of it, a fix of that fix, another change and a
+/// revert of that change. `bp` (checked out) has the feature picked with -x
+/// and the other change picked under a new Change-Id, keeping the upstream
+/// one as Was-Change-Id.
+pub fn backport_fixture() -> Fixture {
+    let repo = TestRepo::new();
+
+    repo.git(&["checkout", "-q", "-b", "up"]);
+    let feature = repo.commit("feature.c", "feature\n", &format!("Add feature\n\nChange-Id: {}", FEATURE_CHANGE_ID));
+    let other = repo.commit("other.c", "other\n", &format!("Add other\n\nChange-Id: {}", OTHER_CHANGE_ID));
+    let fix = repo.commit("feature.c", "feature, fixed\n", &format!("Fix feature\n\nFixes: {} (\"Add feature\")", &feature[..12]));
+    let fix_of_fix = repo.commit("feature.c", "feature, really fixed\n", &format!("Fix the feature fix\n\nFixes: {} (\"Fix feature\")", &fix[..12]));
+    repo.git(&["revert", "--no-edit", &other]);
+    let revert = repo.git(&["rev-parse", "HEAD"]);
+    let unrelated = repo.commit("unrelated.c", "unrelated\n", "Add unrelated");
+
+    repo.git(&["checkout", "-q", "-b", "bp", "main"]);
+    repo.git(&["cherry-pick", "-x", &feature]);
+    repo.git(&["cherry-pick", &other]);
+    repo.git(&["commit", "-q", "--amend", "-m",
+               &format!("Add other\n\nChange-Id: {}\nWas-Change-Id: {}", BACKPORT_CHANGE_ID, OTHER_CHANGE_ID)]);
+
+    Fixture {
+        repo,
+        feature,
+        other,
+        fix,
+        fix_of_fix,
+        revert,
+        unrelated,
+    }
+}
@@ -0,0 +1,34 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+mod common;
+
+#[test]
+fn inserts_prerequisites_before_the_first_commit_descending_from_them() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    repo.git(&["checkout", "-q", "up"]);
+    let finish = repo.commit("feature.c", "feature, finished\n", "Finish feature");
+    repo.git(&["checkout", "-q", "bp"]);
+    repo.write("commits", &format!("{}\n{}\n{}\n", fixture.fix, fixture.unrelated, finish));
+
+    // The unrelated commit was made after the fix of the fix the last commit needs
+    let stdout = repo.bp_ok(&["deps", "-i", "-F", "commits", "--ref", "up"]);
+    assert_eq!(stdout, "Inserted 1 prerequisites into commits\n");
+    assert_eq!(repo.read("commits"), format!(
+        "# vim: ft=gitbackportcommits\n\
+         {} Fix feature\n\
+         # prerequisite of {}\n\
+         {} Fix the feature fix\n\
+         {} Add unrelated\n\
+         {} Finish feature\n",
+        fixture.fix, &finish[..12], fixture.fix_of_fix, fixture.unrelated, finish,
+    ));
+
+    repo.bp_ok(&["lint", "-F", "commits", "--ref", "up"]);
+    repo.bp_ok(&["pick", "--apply", "-F", "commits"]);
+    assert_eq!(repo.read("feature.c"), "feature, finished\n");
+}
@@ -0,0 +1,26 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+mod common;
+
+#[test]
+fn compares_squashed_originals_as_one_change() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    repo.git(&["cherry-pick", "-n", &fixture.fix]);
+    repo.git(&["cherry-pick", "-n", &fixture.fix_of_fix]);
+    repo.git(&["commit", "-q", "-m", &format!(
+        "Fix feature\n\n(cherry picked from commit {})\n(cherry picked from commit {})",
+        fixture.fix_of_fix, fixture.fix,
+    )]);
+
+    let stdout = repo.bp_ok(&["--format", "json", "diff", "HEAD", "--ref", "up"]);
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let diff = &report["commits"][0];
+    assert_eq!(diff["originals"][0]["hash"], fixture.fix.as_str());
+    assert_eq!(diff["originals"][1]["hash"], fixture.fix_of_fix.as_str());
+    assert_eq!(diff["state"], "clean");
+}
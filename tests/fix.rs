@@ -0,0 +1,78 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+mod common;
+
+/// The lines `fix` prints for the fix chain of the picked feature, fix of the fix first
+fn fix_chain(fixture: &common::Fixture, backported: &str) -> String {
+    format!(
+        "# fix chain: {feature} -> {fix} -> {fix_of_fix} (backported as {backported})\n\
+         {fix_of_fix_full} Fix the feature fix\n\
+         # fix chain: {feature} -> {fix} (backported as {backported})\n\
+         {fix_full} Fix feature\n",
+        feature = &fixture.feature[..12],
+        fix = &fixture.fix[..12],
+        fix_of_fix = &fixture.fix_of_fix[..12],
+        fix_of_fix_full = fixture.fix_of_fix,
+        fix_full = fixture.fix,
+    )
+}
+
+#[test]
+fn finds_fixes_of_fixes_and_warns_about_reverts() {
+    let fixture = common::backport_fixture();
+    let backported = fixture.repo.git(&["rev-parse", "--short=12", "bp~1"]);
+
+    let output = fixture.repo.bp(&["fix", "--base", "main", "--ref", "up"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, format!("# vim: ft=gitbackportcommits\n{}", fix_chain(&fixture, &backported)));
+    assert!(!stdout.contains(&fixture.unrelated));
+
+    // The revert of the commit picked under a new Change-Id is only reported
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("{} Revert \"Add other\" (reverts {}", &fixture.revert[..12], &fixture.other[..12])));
+    assert!(stderr.contains("--include-reverts"));
+}
+
+#[test]
+fn includes_reverts_of_commits_picked_under_a_new_change_id() {
+    let fixture = common::backport_fixture();
+    let backported = fixture.repo.git(&["rev-parse", "--short=12", "bp~1"]);
+    let picked_other = fixture.repo.git(&["rev-parse", "--short=12", "bp"]);
+
+    let stdout = fixture.repo.bp_ok(&["fix", "--base", "main", "--ref", "up", "--include-reverts"]);
+    assert_eq!(stdout, format!(
+        "# vim: ft=gitbackportcommits\n{}# revert of {} (backported as {})\n{} Revert \"Add other\"\n",
+        fix_chain(&fixture, &backported), &fixture.other[..12], picked_other, fixture.revert,
+    ));
+}
+
+#[test]
+fn finds_nothing_once_everything_is_backported() {
+    let fixture = common::backport_fixture();
+    for commit in [&fixture.fix, &fixture.fix_of_fix, &fixture.revert] {
+        fixture.repo.git(&["cherry-pick", "-x", commit]);
+    }
+
+    let stdout = fixture.repo.bp_ok(&["fix", "--base", "main", "--ref", "up", "--include-reverts"]);
+    assert_eq!(stdout, "# vim: ft=gitbackportcommits\n");
+}
+
+#[test]
+fn reports_how_originals_were_found_and_fixes_applied_as_json() {
+    let fixture = common::backport_fixture();
+    fixture.repo.git(&["cherry-pick", "-x", &fixture.fix]);
+
+    let stdout = fixture.repo.bp_ok(&["--format", "json", "fix", "--base", "main", "--ref", "up"]);
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let feature = &report["commits"][0]["originals"][0];
+    assert_eq!(feature["hash"], fixture.feature.as_str());
+    assert_eq!(feature["found_by"], "change-id");
+    assert_eq!(feature["fixes"][0]["hash"], fixture.fix.as_str());
+    assert_eq!(feature["fixes"][0]["applied_by"], "cherry-pick");
+    assert_eq!(report["commits"][1]["originals"][0]["found_by"], "was-change-id");
+}
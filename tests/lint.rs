@@ -0,0 +1,41 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+mod common;
+
+/// Hashes listed in a commits file, in file order
+fn listed_hashes(content: &str) -> Vec<String> {
+    content.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().next().map(str::to_string))
+        .collect()
+}
+
+#[test]
+fn accepts_and_fixes_to_the_order_sort_writes_which_applies_cleanly() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    let shuffled = format!("{}\n{}\n{}\n", fixture.fix_of_fix, fixture.unrelated, fixture.fix);
+    repo.write("sorted", &shuffled);
+    repo.write("fixed", &shuffled);
+
+    repo.bp_ok(&["sort", "-i", "-F", "sorted", "--ref", "up"]);
+    repo.bp_ok(&["lint", "-F", "sorted", "--ref", "up"]);
+
+    let stdout = repo.bp_ok(&["lint", "--fix", "-F", "fixed", "--ref", "up"]);
+    assert_eq!(stdout, format!(
+        "fixed:1: {} is listed before {} on line 3, which it descends from (fixed)\n\
+         fixed:2: {} is listed before {} on line 3, which it descends from (fixed)\n",
+        &fixture.fix_of_fix[..12], &fixture.fix[..12], &fixture.unrelated[..12], &fixture.fix[..12],
+    ));
+    assert_eq!(listed_hashes(&repo.read("fixed")), listed_hashes(&repo.read("sorted")));
+    assert_eq!(listed_hashes(&repo.read("sorted")), vec![fixture.fix.clone(), fixture.fix_of_fix.clone(), fixture.unrelated.clone()]);
+
+    // Commits are picked in file order, so each fix finds the code it fixes
+    repo.bp_ok(&["pick", "--apply", "-F", "sorted"]);
+    assert_eq!(repo.read("feature.c"), "feature, really fixed\n");
+    assert_eq!(repo.git(&["log", "--format=%s", "-3"]), "Add unrelated\nFix the feature fix\nFix feature");
+}
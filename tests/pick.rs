@@ -0,0 +1,148 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+mod common;
+
+#[test]
+fn prints_cherry_pick_commands() {
+    let fixture = common::backport_fixture();
+    let stdout = fixture.repo.bp_ok(&["pick", &fixture.fix[..12], &fixture.fix_of_fix]);
+    assert_eq!(stdout, format!(
+        "git cherry-pick -x --signoff {}\ngit cherry-pick -x --signoff {}\n",
+        &fixture.fix[..12], fixture.fix_of_fix,
+    ));
+}
+
+#[test]
+fn comments_out_commands_after_an_edit() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    repo.write("commits", &format!("edit {}\npick {}\n", fixture.fix, fixture.fix_of_fix));
+
+    let stdout = repo.bp_ok(&["pick", "-F", "commits"]);
+    assert_eq!(stdout, format!(
+        "git cherry-pick -x --signoff {} && echo 'Stopped at {}; amend it, then run the remaining commands'\n\
+         # Run the commands below once the commit is amended\n\
+         # git cherry-pick -x --signoff {}\n",
+        fixture.fix, &fixture.fix[..12], fixture.fix_of_fix,
+    ));
+
+    // Piped to a shell without -e, nothing runs past the edit
+    let output = repo.command("sh").args(["-c", &stdout]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(repo.git(&["log", "-1", "--format=%s"]), "Fix feature");
+}
+
+#[test]
+fn applies_commits_file() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    repo.write("commits", &format!("# fix chain\n{} Fix feature\n{}\n", fixture.fix, &fixture.fix_of_fix[..12]));
+
+    let output = repo.bp(&["pick", "--apply", "-F", "commits"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Successfully picked 2 commits"));
+
+    assert_eq!(repo.git(&["log", "--format=%s", "main..HEAD"]), "Fix the feature fix\nFix feature\nAdd other\nAdd feature");
+    assert_eq!(repo.git(&["log", "-1", "--format=%b"]), format!(
+        "Fixes: {} (\"Fix feature\")\n(cherry picked from commit {})\nSigned-off-by: Test <test@example.com>",
+        &fixture.fix[..12], fixture.fix_of_fix,
+    ));
+    assert_eq!(repo.read("feature.c"), "feature, really fixed\n");
+    assert_eq!(repo.git(&["status", "--porcelain", "--untracked-files=no"]), "");
+}
+
+#[test]
+fn stops_on_conflicts_and_aborts() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    let head = repo.git(&["rev-parse", "HEAD"]);
+
+    // Without the fix it builds on, the fix of the fix does not apply
+    let output = repo.bp(&["pick", "--apply", &fixture.fix_of_fix]);
+    assert!(!output.status.success());
+
+    repo.bp_ok(&["pick", "--abort"]);
+    assert_eq!(repo.git(&["rev-parse", "HEAD"]), head);
+    assert_eq!(repo.read("feature.c"), "feature\n");
+}
+
+#[test]
+fn amends_commits_picked_with_git_cherry_pick_continue() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+
+    let output = repo.bp(&["pick", "--apply", "--trailer", "Upstream-Commit: %h", &fixture.fix_of_fix]);
+    assert!(!output.status.success());
+
+    // Resolving the conflict with git itself commits the pick without the trailer
+    repo.write("feature.c", "feature, really fixed\n");
+    repo.git(&["add", "feature.c"]);
+    repo.git(&["cherry-pick", "--continue"]);
+    repo.bp_ok(&["pick", "--continue"]);
+
+    assert_eq!(repo.git(&["log", "-1", "--format=%b"]), format!(
+        "Fixes: {} (\"Fix feature\")\n(cherry picked from commit {})\nSigned-off-by: Test <test@example.com>\nUpstream-Commit: {}",
+        &fixture.fix[..12], fixture.fix_of_fix, &fixture.fix_of_fix[..12],
+    ));
+    assert_eq!(repo.git(&["log", "--format=%s", "main..HEAD"]), "Fix the feature fix\nAdd other\nAdd feature");
+}
+
+#[test]
+fn rejects_leading_fixup_before_touching_the_tree() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    let head = repo.git(&["rev-parse", "HEAD"]);
+    repo.write("commits", &format!("drop {}\nfixup {}\npick {}\n", fixture.fix, fixture.fix_of_fix, fixture.unrelated));
+
+    for args in [&["pick", "--apply", "-F", "commits"][..], &["pick", "--todo", "-F", "commits"]] {
+        let output = repo.bp(args);
+        assert!(!output.status.success());
+        assert!(String::from_utf8(output.stderr).unwrap().contains(&format!("Cannot fixup {}", fixture.fix_of_fix)));
+    }
+    assert_eq!(repo.git(&["rev-parse", "HEAD"]), head);
+    assert!(!repo.path().join(".git/bp-pick").exists());
+}
+
+#[test]
+fn applies_sorted_files_keeping_fixups_with_their_target() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    repo.write("commits", &format!("pick {}\nfixup {}\n", fixture.unrelated, fixture.fix));
+
+    // The fixup is older than the commit it is folded into, but has to stay after it
+    repo.bp_ok(&["sort", "-i", "-F", "commits", "--ref", "up"]);
+    assert_eq!(repo.read("commits"), format!(
+        "# vim: ft=gitbackportcommits\npick {} Add unrelated\nfixup {} Fix feature\n",
+        fixture.unrelated, fixture.fix,
+    ));
+
+    repo.bp_ok(&["pick", "--apply", "-F", "commits"]);
+    assert_eq!(repo.git(&["log", "--format=%s", "main..HEAD"]), "Add unrelated\nAdd other\nAdd feature");
+    assert_eq!(repo.read("feature.c"), "feature, fixed\n");
+}
+
+#[test]
+fn rebases_merges_relative_to_the_configured_mainline() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    repo.git(&["checkout", "-q", "-b", "side", "main"]);
+    repo.commit("side.c", "side\n", "Add side");
+    repo.git(&["checkout", "-q", "-b", "merged", "main"]);
+    repo.commit("mainline.c", "mainline\n", "Add mainline");
+    repo.git(&["merge", "-q", "--no-ff", "-m", "Merge side", "side"]);
+    let merge = repo.git(&["rev-parse", "HEAD"]);
+    repo.git(&["checkout", "-q", "bp"]);
+    repo.git(&["config", "bp.pick.mainline", "1"]);
+
+    let todo = repo.bp_ok(&["pick", "--todo", &merge]);
+    assert_eq!(todo, format!("# Merge side\nexec git cherry-pick -x --signoff -m 1 {}\n", merge));
+
+    repo.bp_ok(&["pick", "--rebase", &merge]);
+    assert_eq!(repo.git(&["log", "-1", "--format=%s"]), "Merge side");
+    assert_eq!(repo.read("side.c"), "side\n");
+    assert!(!repo.path().join("mainline.c").exists());
+}
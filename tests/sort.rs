@@ -0,0 +1,95 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+mod common;
+
+#[test]
+fn sorts_file_in_place_keeping_comments() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    repo.write("commits", &format!(
+        "# fixes\n{}\n\n# feature first\n{} Fix feature\n{}\n",
+        &fixture.fix_of_fix[..10], &fixture.fix[..12], fixture.unrelated,
+    ));
+
+    let stdout = repo.bp_ok(&["sort", "-F", "commits", "-i", "--ref", "up"]);
+    assert_eq!(stdout, "Updated 3 commits in commits\n");
+    assert_eq!(repo.read("commits"), format!(
+        "# vim: ft=gitbackportcommits\n\
+         \n\
+         # feature first\n\
+         {} Fix feature\n\
+         # fixes\n\
+         {} Fix the feature fix\n\
+         {} Add unrelated\n",
+        fixture.fix, fixture.fix_of_fix, fixture.unrelated,
+    ));
+}
+
+#[test]
+fn warns_about_lines_that_are_not_commits() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    repo.write("commits", &format!("{}\nfix-of-fix\n", fixture.fix));
+
+    let output = repo.bp(&["sort", "-F", "commits", "--ref", "up"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{}\n", fixture.fix));
+    assert!(String::from_utf8(output.stderr).unwrap()
+        .contains("WARNING: commits:2: not a commit, treating it as a comment: fix-of-fix"));
+}
+
+#[test]
+fn prints_sorted_commits_keeping_change_ids() {
+    let fixture = common::backport_fixture();
+    let stdout = fixture.repo.bp_ok(&["sort", "--ref", "up", &fixture.feature[..12], &fixture.other[..12]]);
+    assert_eq!(stdout, format!("{}\n{}\n", &fixture.feature[..12], &fixture.other[..12]));
+}
+
+#[test]
+fn places_commits_not_on_any_reference_by_date() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    let backported = repo.git(&["rev-parse", "bp"]);
+    repo.write("commits", &format!("{}\n{}\n{}\n", fixture.feature, backported, fixture.unrelated));
+
+    let output = repo.bp(&["sort", "-F", "commits", "-i", "--ref", "up"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(&format!("  {} Add other", backported)));
+    // The backported commit was made after all upstream ones
+    assert_eq!(repo.read("commits"), format!(
+        "# vim: ft=gitbackportcommits\n\
+         {} {} Add feature\n\
+         {} Add unrelated\n\
+         # WARNING: not reachable from any --ref, placed by committer date\n\
+         {} {} Add other\n",
+        fixture.feature, common::FEATURE_CHANGE_ID, fixture.unrelated, backported, common::BACKPORT_CHANGE_ID,
+    ));
+
+    // Once a reference reaches it the warning goes, and the commits of both
+    // references, which are not related to each other, interleave by date
+    let stdout = repo.bp_ok(&["sort", "-F", "commits", "-i", "--ref", "up", "--ref", "bp"]);
+    assert_eq!(stdout, "Updated 3 commits in commits\n");
+    assert_eq!(repo.read("commits"), format!(
+        "# vim: ft=gitbackportcommits\n{} {} Add feature\n{} Add unrelated\n{} {} Add other\n",
+        fixture.feature, common::FEATURE_CHANGE_ID, fixture.unrelated, backported, common::BACKPORT_CHANGE_ID,
+    ));
+}
+
+#[test]
+fn honors_git_dir() {
+    let fixture = common::backport_fixture();
+    let other = common::TestRepo::new();
+
+    // Run from another repository, pointing git at the fixture
+    let output = other.bp_command(&["sort", "--ref", "up", &fixture.fix[..12], &fixture.feature[..12]])
+        .env("GIT_DIR", fixture.repo.path().join(".git"))
+        .env("GIT_WORK_TREE", fixture.repo.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{}\n{}\n", &fixture.feature[..12], &fixture.fix[..12]));
+}
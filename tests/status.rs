@@ -0,0 +1,23 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+mod common;
+
+#[test]
+fn matches_commits_picked_without_origin_by_patch_id() {
+    let fixture = common::backport_fixture();
+    let repo = &fixture.repo;
+    repo.git(&["cherry-pick", &fixture.unrelated]);
+    repo.commit("downstream.c", "downstream\n", "Add downstream");
+
+    let stdout = repo.bp_ok(&["--format", "json", "status", "--base", "main", "--ref", "up"]);
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let unrelated = &report["commits"][2];
+    assert_eq!(unrelated["originals"][0]["hash"], fixture.unrelated.as_str());
+    assert_eq!(unrelated["originals"][0]["found_by"], "patch-id");
+    assert_eq!(report["commits"][3]["downstream_only"], true);
+    assert_eq!(report["summary"]["backported"], 3);
+}
@@ -0,0 +1,42 @@
+/*
+ * SPDX-FileCopyrightText: 2025 2025 Chen Linxuan <me@black-desk.cn>
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+mod common;
+
+const PLUGIN: &str = include_str!("../vim/ftplugin/gitbackportcommits.vim");
+
+#[test]
+fn installs_plugin_keeping_existing_files_unless_forced() {
+    let repo = common::TestRepo::new();
+    let plugin = "vimfiles/ftplugin/gitbackportcommits.vim";
+
+    repo.bp_ok(&["vim", "--vim-dir", "vimfiles"]);
+    assert_eq!(repo.read(plugin), PLUGIN);
+
+    repo.write(plugin, "\" customized\n");
+    repo.bp_ok(&["vim", "--vim-dir", "vimfiles"]);
+    assert_eq!(repo.read(plugin), "\" customized\n");
+
+    repo.bp_ok(&["vim", "--vim-dir", "vimfiles", "--force"]);
+    assert_eq!(repo.read(plugin), PLUGIN);
+}
+
+#[test]
+fn installs_plugin_for_vim_and_neovim_by_default() {
+    let repo = common::TestRepo::new();
+    repo.bp_ok(&["vim"]);
+    assert_eq!(repo.read(".vim/ftplugin/gitbackportcommits.vim"), PLUGIN);
+    assert_eq!(repo.read(".config/nvim/ftplugin/gitbackportcommits.vim"), PLUGIN);
+}
+
+#[test]
+fn rejects_json_output() {
+    let repo = common::TestRepo::new();
+    let output = repo.bp(&["--format", "json", "vim", "--vim-dir", "vimfiles"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("--format json is not supported by vim"));
+    assert!(!repo.path().join("vimfiles").exists());
+}